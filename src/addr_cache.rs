@@ -0,0 +1,152 @@
+use std::{
+    io::{self, Write as _},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use iroh::{EndpointAddr, EndpointId, RelayUrl, TransportAddr, endpoint::ConnectionType};
+
+pub const ADDR_CACHE_FILE_NAME: &str = "irohssh_known_addrs";
+
+/// Peers remembered at most, the least recently connected ones are dropped first.
+const MAX_ENTRIES: usize = 256;
+
+/// On-disk cache of the last known direct addresses and home relay per peer.
+///
+/// Clients seed their endpoint from it before connecting, so reconnects to a
+/// known machine don't have to wait for discovery. Each line holds
+/// `<endpoint_id> <addr>...`, where an addr is an `ip:port` or a relay URL.
+#[derive(Debug, Clone)]
+pub struct AddrCache {
+    path: PathBuf,
+}
+
+impl AddrCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The cache file inside an ssh directory, e.g. `~/.ssh`.
+    pub fn in_dir(ssh_dir: &Path) -> Self {
+        Self::new(ssh_dir.join(ADDR_CACHE_FILE_NAME))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, endpoint_id: &EndpointId) -> Option<EndpointAddr> {
+        self.load()
+            .ok()?
+            .into_iter()
+            .find(|addr| addr.id == *endpoint_id)
+    }
+
+    /// Remembers the path a connection to `endpoint_id` is using.
+    ///
+    /// Only a confirmed direct path replaces the cached ip address, a relayed or
+    /// mixed one (still hole punching) keeps it. A direct path keeps the cached home relay.
+    pub(crate) fn record(&self, endpoint_id: EndpointId, path: ConnectionType) -> io::Result<()> {
+        let mut addr = EndpointAddr::new(endpoint_id);
+        match path {
+            ConnectionType::Direct(ip) => addr = addr.with_ip_addr(ip),
+            ConnectionType::Relay(url) | ConnectionType::Mixed(_, url) => {
+                addr = addr.with_relay_url(url)
+            }
+            _ => return Ok(()),
+        }
+
+        let mut entries = self.load()?;
+        if let Some(previous) = entries
+            .iter()
+            .position(|addr| addr.id == endpoint_id)
+            .map(|i| entries.remove(i))
+        {
+            if addr.ip_addrs().next().is_none() {
+                addr = addr.with_addrs(previous.ip_addrs().copied().map(TransportAddr::Ip));
+            }
+            if addr.relay_urls().next().is_none() {
+                addr = addr.with_addrs(previous.relay_urls().cloned().map(TransportAddr::Relay));
+            }
+        }
+
+        entries.push(addr);
+        let skip = entries.len().saturating_sub(MAX_ENTRIES);
+        self.store(&entries[skip..])
+    }
+
+    fn load(&self) -> io::Result<Vec<EndpointAddr>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = Vec::new();
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            let Some(Ok(id)) = parts.next().map(EndpointId::from_str) else {
+                continue;
+            };
+            let addrs = parts.filter_map(|part| match SocketAddr::from_str(part) {
+                Ok(ip) => Some(TransportAddr::Ip(ip)),
+                Err(_) => RelayUrl::from_str(part).ok().map(TransportAddr::Relay),
+            });
+            entries.push(EndpointAddr::from_parts(id, addrs));
+        }
+        Ok(entries)
+    }
+
+    fn store(&self, entries: &[EndpointAddr]) -> io::Result<()> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        for entry in entries {
+            write!(file, "{}", entry.id)?;
+            for ip in entry.ip_addrs() {
+                write!(file, " {ip}")?;
+            }
+            for url in entry.relay_urls() {
+                write!(file, " {url}")?;
+            }
+            writeln!(file)?;
+        }
+        file.persist(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    fn record_keeps_the_other_half_of_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AddrCache::in_dir(dir.path());
+        let id = SecretKey::generate(&mut rand::rng()).public();
+        let relay = RelayUrl::from_str("https://relay.example.com").unwrap();
+        let ip = SocketAddr::from(([192, 168, 1, 10], 4242));
+        let candidate = SocketAddr::from(([10, 0, 0, 1], 4242));
+
+        assert!(cache.get(&id).is_none());
+        cache
+            .record(id, ConnectionType::Relay(relay.clone()))
+            .unwrap();
+        cache.record(id, ConnectionType::Direct(ip)).unwrap();
+
+        let addr = cache.get(&id).unwrap();
+        assert_eq!(addr.ip_addrs().collect::<Vec<_>>(), vec![&ip]);
+        assert_eq!(addr.relay_urls().collect::<Vec<_>>(), vec![&relay]);
+
+        // the next connection starts relayed, trying an address that may not work
+        cache
+            .record(id, ConnectionType::Mixed(candidate, relay.clone()))
+            .unwrap();
+        cache.record(id, ConnectionType::None).unwrap();
+        let addr = cache.get(&id).unwrap();
+        assert_eq!(addr.ip_addrs().collect::<Vec<_>>(), vec![&ip]);
+        assert_eq!(cache.load().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr as _,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use iroh::{EndpointAddr, EndpointId, TransportAddr, endpoint::Connection};
+use tokio::{
+    io::{
+        AsyncBufReadExt as _, AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _,
+        BufReader,
+    },
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{
+    CloseCode, Error, IrohSsh, RetryPolicy, TargetRequest, Tunnel,
+    pump::{PUMP_BUFFER_SIZE, pump},
+    runtime_dir,
+};
+
+pub const AGENT_SOCKET_NAME: &str = "agent.sock";
+
+/// Longest request line a client may send.
+const MAX_REQUEST_LEN: u64 = 512;
+
+/// Where `iroh-ssh agent` listens and `iroh-ssh proxy` looks for it.
+pub(crate) fn default_socket_path() -> io::Result<PathBuf> {
+    Ok(runtime_dir::runtime_dir()?.join(AGENT_SOCKET_NAME))
+}
+
+/// A tunnel `proxy` asks the agent to open, sent as one line:
+/// `open <endpoint_id> <target_name|-> <timeout_secs|-> <retries|wait> [<ip:port|relay_url>...]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AgentRequest {
+    pub endpoint_id: EndpointId,
+    pub request: TargetRequest,
+    pub connect_timeout: Option<Duration>,
+    /// `None` waits for the host to come online.
+    pub retries: Option<u32>,
+    /// Addresses from a ticket or `--addr`, tried besides the cached and discovered ones.
+    pub addrs: Vec<TransportAddr>,
+}
+
+impl AgentRequest {
+    fn to_line(&self) -> String {
+        let target = match &self.request {
+            TargetRequest::Ssh => "-".to_string(),
+            TargetRequest::Named(name) => name.clone(),
+        };
+        let timeout = self
+            .connect_timeout
+            .map_or("-".to_string(), |t| t.as_secs().to_string());
+        let retries = self.retries.map_or("wait".to_string(), |r| r.to_string());
+        let mut line = format!("open {} {target} {timeout} {retries}", self.endpoint_id);
+        for addr in &self.addrs {
+            match addr {
+                TransportAddr::Ip(addr) => line.push_str(&format!(" {addr}")),
+                TransportAddr::Relay(url) => line.push_str(&format!(" {url}")),
+                _ => {}
+            }
+        }
+        line.push('\n');
+        line
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        if parts.next()? != "open" {
+            return None;
+        }
+        let endpoint_id = EndpointId::from_str(parts.next()?).ok()?;
+        let request = match parts.next()? {
+            "-" => TargetRequest::Ssh,
+            name => TargetRequest::Named(name.to_string()),
+        };
+        let connect_timeout = match parts.next()? {
+            "-" => None,
+            secs => Some(Duration::from_secs(secs.parse().ok()?)),
+        };
+        let retries = match parts.next()? {
+            "wait" => None,
+            retries => Some(retries.parse().ok()?),
+        };
+        let addrs = parts
+            .map(|addr| match addr.parse::<SocketAddr>() {
+                Ok(addr) => Some(TransportAddr::Ip(addr)),
+                Err(_) => Some(TransportAddr::Relay(addr.parse().ok()?)),
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            endpoint_id,
+            request,
+            connect_timeout,
+            retries,
+            addrs,
+        })
+    }
+}
+
+/// Why [`connect`] could not hand back a tunnel, or why [`run_tunnel`] ended badly.
+#[derive(Debug)]
+pub(crate) enum AgentError {
+    /// No agent is listening, run standalone instead.
+    Unavailable(io::Error),
+    /// The agent is running but could not open the tunnel, or the server closed it.
+    Failed { exit_code: i32, message: String },
+}
+
+/// Asks the agent at `socket` for a tunnel and returns the local end of it.
+pub(crate) async fn connect(socket: &Path, req: &AgentRequest) -> Result<UnixStream, AgentError> {
+    let mut stream = UnixStream::connect(socket)
+        .await
+        .map_err(AgentError::Unavailable)?;
+    stream
+        .write_all(req.to_line().as_bytes())
+        .await
+        .map_err(AgentError::Unavailable)?;
+
+    read_status(&mut stream).await?;
+    Ok(stream)
+}
+
+/// Pumps `input` to the tunnel and the tunnel to `output` until the agent reports how it ended.
+///
+/// The agent frames what it sends after `ok` as `<u32 len><bytes>` chunks, ended by an empty
+/// chunk and a status line like the one [`connect`] reads, so the close code reaches ssh.
+pub(crate) async fn run_tunnel<R, W>(
+    stream: UnixStream,
+    mut input: R,
+    mut output: W,
+) -> Result<(), AgentError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (mut read, mut write) = stream.into_split();
+    let up = async {
+        pump(&mut input, &mut write).await.ok();
+        write.shutdown().await.ok();
+        // stdin can't be cancelled, so the end of the download decides when we're done
+        std::future::pending::<()>().await
+    };
+    let down = async {
+        read_frames(&mut read, &mut output)
+            .await
+            .map_err(AgentError::Unavailable)?;
+        read_status(&mut read).await
+    };
+    tokio::select! {
+        res = down => res,
+        () = up => unreachable!(),
+    }
+}
+
+/// Reads an `ok` or `err <exit_code> <message>` line from the agent.
+async fn read_status<R: AsyncRead + Unpin>(stream: &mut R) -> Result<(), AgentError> {
+    // Read the reply byte by byte so nothing of the tunnel data gets buffered away.
+    let mut reply = Vec::new();
+    loop {
+        let byte = stream.read_u8().await.map_err(AgentError::Unavailable)?;
+        if byte == b'\n' {
+            break;
+        }
+        if reply.len() as u64 >= MAX_REQUEST_LEN {
+            return Err(AgentError::Unavailable(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "agent reply too long",
+            )));
+        }
+        reply.push(byte);
+    }
+    let reply = String::from_utf8_lossy(&reply);
+    if reply == "ok" {
+        return Ok(());
+    }
+    let (exit_code, message) = reply
+        .strip_prefix("err ")
+        .and_then(|rest| rest.split_once(' '))
+        .and_then(|(code, message)| Some((code.parse().ok()?, message.to_string())))
+        .unwrap_or((1, format!("unexpected agent reply '{reply}'")));
+    Err(AgentError::Failed { exit_code, message })
+}
+
+/// The `ok` or `err <exit_code> <message>` line [`read_status`] parses.
+fn status_line(res: Result<(), Error>) -> String {
+    match res {
+        Ok(()) => "ok\n".to_string(),
+        Err(err) => {
+            let message = err.to_string().replace('\n', " ");
+            let exit_code = crate::api::exit_code(&err.into());
+            format!("err {exit_code} {message}\n")
+        }
+    }
+}
+
+/// Sends `reader` as `<u32 len><bytes>` chunks until EOF, see [`run_tunnel`].
+async fn write_frames<R, W>(reader: &mut R, writer: &mut W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; PUMP_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_u32(n as u32).await?;
+        writer.write_all(&buf[..n]).await?;
+        writer.flush().await?;
+    }
+}
+
+/// Copies chunks from [`write_frames`] to `writer` up to the empty one that ends them.
+async fn read_frames<R, W>(reader: &mut R, writer: &mut W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; PUMP_BUFFER_SIZE];
+    loop {
+        let len = reader.read_u32().await? as usize;
+        if len == 0 {
+            return writer.flush().await;
+        }
+        if len > buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "agent chunk too long",
+            ));
+        }
+        reader.read_exact(&mut buf[..len]).await?;
+        writer.write_all(&buf[..len]).await?;
+        writer.flush().await?;
+    }
+}
+
+/// A long-lived client endpoint that keeps one connection per server and
+/// opens tunnels for `iroh-ssh proxy` over a local socket.
+#[derive(Debug)]
+pub struct Agent {
+    iroh_ssh: IrohSsh,
+    /// Keyed by whether the connection is for named targets, which use a different ALPN.
+    pool: Mutex<HashMap<(EndpointId, bool), Connection>>,
+}
+
+impl Agent {
+    pub fn new(iroh_ssh: IrohSsh) -> Self {
+        Self {
+            iroh_ssh,
+            pool: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Binds the agent socket.
+    ///
+    /// A stale socket file is replaced, a live one means another agent runs already.
+    pub fn listen(socket: &Path) -> io::Result<UnixListener> {
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("an agent is already listening on {}", socket.display()),
+            ));
+        }
+        match std::fs::remove_file(socket) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        UnixListener::bind(socket)
+    }
+
+    /// Serves `proxy` clients on `listener` until the process exits.
+    pub async fn serve(self, listener: UnixListener) -> io::Result<()> {
+        let agent = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let agent = agent.clone();
+            tokio::spawn(async move {
+                if let Err(e) = agent.handle(stream).await {
+                    eprintln!("agent: {e}");
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        (&mut reader)
+            .take(MAX_REQUEST_LEN)
+            .read_line(&mut line)
+            .await?;
+        let mut stream = reader.into_inner();
+
+        let Some(req) = AgentRequest::parse(&line) else {
+            stream.write_all(b"err 64 invalid agent request\n").await?;
+            return Ok(());
+        };
+        let mut tunnel = match self.open(&req).await {
+            Ok(tunnel) => tunnel,
+            Err(err) => {
+                stream.write_all(status_line(Err(err)).as_bytes()).await?;
+                return Ok(());
+            }
+        };
+        stream.write_all(b"ok\n").await?;
+
+        let connection = tunnel.connection().clone();
+        let (mut sock_read, mut sock_write) = stream.split();
+        let (mut tunnel_read, mut tunnel_write) = tokio::io::split(&mut tunnel);
+        let up = async {
+            pump(&mut sock_read, &mut tunnel_write).await?;
+            tunnel_write.shutdown().await
+        };
+        // Report the end as soon as the server is done, the proxy then closes its side.
+        let down = async {
+            let res = write_frames(&mut tunnel_read, &mut sock_write).await;
+            // like a direct proxy, only a close code from the server makes this a failure
+            let code = CloseCode::from_connection(&connection)
+                .or_else(|| res.err().as_ref().and_then(CloseCode::from_stream_error));
+            let end = match code {
+                Some(code) => Err(Error::TunnelClosed {
+                    endpoint_id: req.endpoint_id,
+                    code,
+                }),
+                None => Ok(()),
+            };
+            sock_write.write_u32(0).await?;
+            sock_write.write_all(status_line(end).as_bytes()).await?;
+            sock_write.shutdown().await
+        };
+        let (_, down) = tokio::join!(up, down);
+        down
+    }
+
+    /// Opens a tunnel on the pooled connection to the server, connecting first if needed.
+    async fn open(&self, req: &AgentRequest) -> Result<Tunnel, Error> {
+        let key = (
+            req.endpoint_id,
+            matches!(req.request, TargetRequest::Named(_)),
+        );
+        let pooled = self
+            .pool
+            .lock()
+            .expect("pool lock poisoned")
+            .get(&key)
+            .cloned();
+        if let Some(conn) = pooled
+            && conn.close_reason().is_none()
+            && let Ok(tunnel) = IrohSsh::open_on(conn, &req.request).await
+        {
+            return Ok(tunnel);
+        }
+
+        let policy = RetryPolicy {
+            connect_timeout: req.connect_timeout,
+            retries: req.retries,
+            ..Default::default()
+        };
+        let addr = EndpointAddr::from_parts(req.endpoint_id, req.addrs.iter().cloned());
+        let tunnel = self
+            .iroh_ssh
+            .open_with_retry(addr, &req.request, &policy)
+            .await?;
+        self.pool
+            .lock()
+            .expect("pool lock poisoned")
+            .insert(key, tunnel.connection().clone());
+        Ok(tunnel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    fn request_line_roundtrips() {
+        let req = AgentRequest {
+            endpoint_id: SecretKey::generate(&mut rand::rng()).public(),
+            request: TargetRequest::Named("nas".to_string()),
+            connect_timeout: Some(Duration::from_secs(5)),
+            retries: None,
+            addrs: vec![
+                TransportAddr::Ip("192.168.1.5:40000".parse().unwrap()),
+                TransportAddr::Relay("https://relay.example.com".parse().unwrap()),
+            ],
+        };
+        assert_eq!(AgentRequest::parse(&req.to_line()), Some(req));
+        assert_eq!(AgentRequest::parse("open nonsense - - 0"), None);
+    }
+
+    #[tokio::test]
+    async fn overlong_reply_is_not_parsed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("agent.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let reply = vec![b'x'; MAX_REQUEST_LEN as usize * 2];
+            stream.write_all(&reply).await.ok();
+        });
+
+        let req = AgentRequest {
+            endpoint_id: SecretKey::generate(&mut rand::rng()).public(),
+            request: TargetRequest::Ssh,
+            connect_timeout: None,
+            retries: None,
+            addrs: Vec::new(),
+        };
+        match connect(&socket, &req).await {
+            Err(AgentError::Unavailable(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            res => panic!("expected an invalid reply, got {res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn tunnel_end_reaches_the_proxy() {
+        use crate::{CloseCode, MemoryConnector};
+        use iroh::{Endpoint, RelayMode, protocol::Router};
+        use std::net::Ipv4Addr;
+
+        let (connector, mut rx) = MemoryConnector::new(1024);
+        let server_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let server = IrohSsh::builder()
+            .target_connector(connector)
+            .build_on(server_endpoint.clone())
+            .await
+            .unwrap();
+        let _router = server
+            .accept_on(Router::builder(server_endpoint.clone()))
+            .spawn();
+        let client_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let client = IrohSsh::builder().build_on(client_endpoint).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("agent.sock");
+        let listener = Agent::listen(&socket).unwrap();
+        tokio::spawn(Agent::new(client).serve(listener));
+
+        let req = AgentRequest {
+            endpoint_id: server_endpoint.id(),
+            request: TargetRequest::Named("api".to_string()),
+            connect_timeout: None,
+            retries: Some(0),
+            addrs: server_endpoint
+                .bound_sockets()
+                .into_iter()
+                .filter(|addr| addr.is_ipv4())
+                .map(|addr| TransportAddr::Ip((Ipv4Addr::LOCALHOST, addr.port()).into()))
+                .collect(),
+        };
+
+        // a tunnel the server finishes cleanly
+        let app = tokio::spawn(async move {
+            let mut app = rx.recv().await.unwrap();
+            let mut buf = [0u8; 4];
+            app.stream.read_exact(&mut buf).await.unwrap();
+            app.stream.write_all(b"pong").await.unwrap();
+            app.stream.shutdown().await.unwrap();
+            assert_eq!(&buf, b"ping");
+            rx
+        });
+        let stream = connect(&socket, &req).await.unwrap();
+        let mut output = Vec::new();
+        run_tunnel(stream, &b"ping"[..], &mut output).await.unwrap();
+        assert_eq!(output, b"pong");
+
+        // the server resets the next one since nothing takes its tunnels anymore
+        drop(app.await.unwrap());
+        let res = match connect(&socket, &req).await {
+            Ok(stream) => run_tunnel(stream, tokio::io::empty(), tokio::io::sink()).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Err(AgentError::Failed { exit_code, .. }) => {
+                assert_eq!(exit_code, 80 + CloseCode::TargetUnavailable.code() as i32)
+            }
+            res => panic!("expected the close code, got {res:?}"),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use iroh::EndpointId;
+
+use crate::target::validate_target_name;
+
+pub const ALIASES_FILE_NAME: &str = "irohssh_aliases";
+
+pub(crate) fn aliases_file(ssh_dir: &Path) -> PathBuf {
+    ssh_dir.join(ALIASES_FILE_NAME)
+}
+
+/// Reads the `<name> <endpoint_id>` lines saved by `iroh-ssh discover`.
+pub fn load_aliases(path: &Path) -> anyhow::Result<Vec<(String, EndpointId)>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut aliases = Vec::new();
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(name), Some(id)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if let Ok(id) = EndpointId::from_str(id) {
+            aliases.push((name.to_string(), id));
+        }
+    }
+    Ok(aliases)
+}
+
+pub(crate) fn resolve_alias(path: &Path, name: &str) -> Option<EndpointId> {
+    load_aliases(path)
+        .ok()?
+        .into_iter()
+        .find(|(alias, _)| alias == name)
+        .map(|(_, id)| id)
+}
+
+/// Saves `name` for `endpoint_id`.
+///
+/// An alias of the same name for another endpoint is only replaced with `replace`,
+/// names come from unauthenticated mDNS adverts.
+pub(crate) fn save_alias(
+    path: &Path,
+    name: &str,
+    endpoint_id: &EndpointId,
+    replace: bool,
+) -> anyhow::Result<()> {
+    validate_target_name(name)?;
+    let mut aliases = load_aliases(path)?;
+    if !replace
+        && let Some((_, existing)) = aliases
+            .iter()
+            .find(|(alias, id)| alias == name && id != endpoint_id)
+    {
+        anyhow::bail!("already an alias for {existing}");
+    }
+    aliases.retain(|(alias, _)| alias != name);
+    aliases.push((name.to_string(), *endpoint_id));
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    for (alias, id) in &aliases {
+        writeln!(tmp, "{alias} {id}")?;
+    }
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    fn saved_aliases_resolve_and_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = aliases_file(dir.path());
+        let old = SecretKey::generate(&mut rand::rng()).public();
+        let new = SecretKey::generate(&mut rand::rng()).public();
+
+        save_alias(&path, "nas", &old, false).unwrap();
+        save_alias(&path, "nas", &old, false).unwrap();
+        assert!(save_alias(&path, "nas", &new, false).is_err());
+        assert_eq!(resolve_alias(&path, "nas"), Some(old));
+
+        save_alias(&path, "nas", &new, true).unwrap();
+        assert_eq!(resolve_alias(&path, "nas"), Some(new));
+        assert_eq!(load_aliases(&path).unwrap().len(), 1);
+        assert!(save_alias(&path, "not a name", &new, true).is_err());
+    }
+}
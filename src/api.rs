@@ -1,21 +1,139 @@
-use std::{path::PathBuf, process::ExitStatus, str::FromStr as _};
+use std::{net::SocketAddr, path::PathBuf, process::ExitStatus, str::FromStr as _, time::Duration};
 
-use anyhow::bail;
+use anyhow::{Context as _, bail};
 use homedir::my_home;
-use iroh::{EndpointId, RelayUrl, SecretKey};
+use iroh::{EndpointAddr, EndpointId, RelayUrl, SecretKey, TransportAddr};
 
 use crate::{
-    IrohSsh,
-    cli::{ConnectArgs, ProxyArgs, ServerArgs},
-    dot_ssh,
+    AddrCache, BenchOptions, BindAddr, Builder, ByteSize, DiscoveryBackend, Error, IpFamily,
+    IrohSsh, LocalAdvert, RetryPolicy, SshHost, TargetRequest, Targets,
+    aliases::{aliases_file, resolve_alias, save_alias},
+    cli::{
+        AgentArgs, BenchArgs, BindOpts, ConnectArgs, ConsoleArgs, DiscoverArgs, MuxCmd, PeersArgs,
+        ProxyArgs, ProxyOpts, QuicOpts, RelaysCmd, ServerArgs,
+    },
+    discover_local, dot_ssh, load_peers, load_relay_map,
+    remote::{format_endpoint_addr, parse_proxy_host},
+    ssh::key_ssh_dir,
 };
 
-fn parse_relay_urls(urls: &[String]) -> anyhow::Result<Vec<RelayUrl>> {
+fn parse_relay_urls(urls: &[String]) -> Result<Vec<RelayUrl>, Error> {
     urls.iter()
-        .map(|s| RelayUrl::from_str(s).map_err(|e| anyhow::anyhow!("invalid relay URL '{s}': {e}")))
+        .map(|s| {
+            RelayUrl::from_str(s).map_err(|e| Error::InvalidRelayUrl {
+                url: s.clone(),
+                reason: e.to_string(),
+            })
+        })
         .collect()
 }
 
+/// Applies `--bind`, `--ipv4-only` and `--ipv6-only` to `builder`.
+fn bind(mut builder: Builder, bind: &BindOpts) -> anyhow::Result<Builder> {
+    let family = match (bind.ipv4_only, bind.ipv6_only) {
+        (true, _) => IpFamily::V4Only,
+        (_, true) => IpFamily::V6Only,
+        _ => IpFamily::Both,
+    };
+    let (mut v4, mut v6) = (None, None);
+    for addr in &bind.bind_addrs {
+        if let BindAddr::Addr(addr) = addr
+            && ((addr.is_ipv4() && family == IpFamily::V6Only)
+                || (addr.is_ipv6() && family == IpFamily::V4Only))
+        {
+            bail!("--bind {addr} contradicts --ipv4-only/--ipv6-only");
+        }
+        for addr in addr.socket_addrs() {
+            let slot = if addr.is_ipv4() { &mut v4 } else { &mut v6 };
+            if slot.replace(addr).is_some() {
+                bail!("--bind takes one IPv4 and one IPv6 address, a port counts as both");
+            }
+            builder = builder.bind_addr(addr);
+        }
+    }
+    Ok(builder.ip_family(family))
+}
+
+/// Applies the `--quic-*` transport options to `builder`.
+fn quic(mut builder: Builder, quic: &QuicOpts) -> Builder {
+    if let Some(size) = quic.quic_stream_window {
+        builder = builder.stream_receive_window(size.0);
+    }
+    if let Some(size) = quic.quic_conn_window {
+        builder = builder.receive_window(size.0);
+    }
+    if let Some(size) = quic.quic_send_window {
+        builder = builder.send_window(size.0);
+    }
+    if let Some(congestion) = quic.quic_congestion {
+        builder = builder.congestion_control(congestion);
+    }
+    // 0 turns either off
+    let secs = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+    if let Some(interval) = quic.quic_keep_alive {
+        builder = builder.keep_alive_interval(secs(interval));
+    }
+    if let Some(timeout) = quic.quic_idle_timeout {
+        builder = builder.max_idle_timeout(secs(timeout));
+    }
+    builder
+}
+
+/// Process exit code for a failed command, distinct per [`Error`] variant.
+///
+/// Codes follow sysexits(3) so scripts can tell the failure classes apart,
+/// tunnels closed by the server exit with 80 plus the [`crate::CloseCode`].
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<Error>() {
+        Some(Error::TunnelClosed { code, .. }) => 80 + code.code() as i32,
+        Some(Error::InvalidRelayUrl { .. }) => 64,
+        Some(Error::KeyDecode { .. }) => 65,
+        Some(Error::KeyNotFound { .. }) => 66,
+        Some(Error::ConnectTimeout { .. }) => 68,
+        Some(Error::SshdUnavailable { .. }) => 69,
+        Some(Error::EndpointBind(_) | Error::BindAddrUnavailable { .. }) => 71,
+        Some(Error::SshSpawn(_)) => 72,
+        Some(Error::KeyIo { .. }) => 74,
+        Some(Error::Connect { .. }) => 75,
+        Some(Error::PathNotAllowed { .. }) => 76,
+        Some(Error::PeerRejected { .. }) => 77,
+        Some(Error::Io(_)) | None => 1,
+    }
+}
+
+/// How long the server banner waits for a home relay before printing its ticket.
+const TICKET_ONLINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Per-attempt timeout for `--wait-for-host` when no `--connect-timeout` is given.
+const WAIT_FOR_HOST_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn retry_policy(proxy: &ProxyOpts, verbose: bool) -> RetryPolicy {
+    let mut policy = RetryPolicy {
+        connect_timeout: proxy.connect_timeout.map(Duration::from_secs),
+        retries: Some(proxy.retries),
+        verbose,
+        ..Default::default()
+    };
+    if proxy.wait_for_host {
+        policy.retries = None;
+        policy
+            .connect_timeout
+            .get_or_insert(WAIT_FOR_HOST_ATTEMPT_TIMEOUT);
+    }
+    policy
+}
+
+/// The client's `~/.ssh`, if that directory exists.
+fn default_ssh_dir() -> Option<PathBuf> {
+    let ssh_dir = my_home().ok().flatten()?.join(".ssh");
+    ssh_dir.exists().then_some(ssh_dir)
+}
+
+/// The client's peer address cache in `~/.ssh`, if that directory exists.
+fn default_addr_cache() -> Option<AddrCache> {
+    default_ssh_dir().map(|ssh_dir| AddrCache::in_dir(&ssh_dir))
+}
+
 pub async fn info_mode(key_dir: Option<PathBuf>) -> anyhow::Result<()> {
     let server_key = dot_ssh(
         &SecretKey::generate(&mut rand::rng()),
@@ -61,6 +179,7 @@ pub async fn info_mode(key_dir: Option<PathBuf>) -> anyhow::Result<()> {
             whoami::username().unwrap_or("UNKNOWN_USER".to_string()),
             key.clone().public()
         );
+        print_published_ticket(&key, false, key_dir.as_deref());
         println!();
     }
 
@@ -72,32 +191,159 @@ pub async fn info_mode(key_dir: Option<PathBuf>) -> anyhow::Result<()> {
             whoami::username().unwrap_or("UNKNOWN_USER".to_string()),
             key.clone().public()
         );
+        print_published_ticket(&key, true, key_dir.as_deref());
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Prints the ticket a running server with `key` keeps next to its keys, if any.
+fn print_published_ticket(key: &SecretKey, service: bool, key_dir: Option<&std::path::Path>) {
+    let Ok(ssh_dir) = key_ssh_dir(service, key_dir) else {
+        return;
+    };
+    let ticket_file = crate::ticket::ticket_file(&ssh_dir);
+    if let Some(ticket) = crate::ticket::read_ticket(&ticket_file)
+        && ticket.endpoint_addr().id == key.public()
+    {
+        println!("  or with its relay and last known addresses:");
+        println!(
+            "  iroh-ssh {}@{ticket}",
+            whoami::username().unwrap_or("UNKNOWN_USER".to_string())
+        );
+        println!("  (from {})", ticket_file.display());
+    }
+}
+
+/// Parses a target host (with an optional `:port`), falling back to aliases saved by `discover`.
+fn resolve_target(host_port: &str) -> anyhow::Result<Option<EndpointAddr>> {
+    if let Some(addr) = parse_proxy_host(host_port)? {
+        return Ok(Some(addr));
+    }
+    let name = host_port.split(':').next().unwrap_or_default();
+    Ok(default_ssh_dir()
+        .and_then(|dir| resolve_alias(&aliases_file(&dir), name))
+        .map(EndpointAddr::new))
+}
+
+pub async fn discover_mode(discover_args: DiscoverArgs) -> anyhow::Result<()> {
+    println!(
+        "Listening for iroh-ssh servers on the local network for {}s...",
+        discover_args.timeout
+    );
+    let servers = discover_local(Duration::from_secs(discover_args.timeout)).await?;
+    if servers.is_empty() {
+        println!(
+            "No servers found (servers show up when started with 'iroh-ssh server --advertise')"
+        );
+        return Ok(());
+    }
+
+    println!();
+    println!(
+        "{:<16}  {:<12}  {:<8}  {:<64}  ADDRESSES",
+        "NAME", "USER", "VERSION", "ENDPOINT ID"
+    );
+    for server in &servers {
+        let addrs = server
+            .addr
+            .ip_addrs()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{:<16}  {:<12}  {:<8}  {:<64}  {addrs}",
+            server.advert.name, server.advert.user, server.advert.version, server.addr.id
+        );
+    }
+    println!();
+
+    let Some(ssh_dir) = default_ssh_dir() else {
+        return Ok(());
+    };
+    let path = aliases_file(&ssh_dir);
+    use std::io::IsTerminal as _;
+    let interactive = std::io::stdin().is_terminal();
+    let save = discover_args.save
+        || (interactive && confirm(&format!("Save them as aliases in {}?", path.display()))?);
+    if save {
+        for server in &servers {
+            let name = &server.advert.name;
+            // adverts aren't authenticated, a LAN host could claim any name
+            let replace = match resolve_alias(&path, name) {
+                Some(existing) if existing != server.addr.id && interactive => confirm(&format!(
+                    "'{name}' is an alias for {existing}, replace it with {}?",
+                    server.addr.id
+                ))?,
+                _ => false,
+            };
+            match save_alias(&path, name, &server.addr.id, replace) {
+                Ok(()) => println!("  iroh-ssh {}@{name}", server.advert.user),
+                Err(e) => eprintln!("  skipped '{name}': {e}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks `question` on the terminal, anything but `y` is a no.
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::Write::flush(&mut std::io::stdout())?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+pub async fn peers_mode(peers_args: PeersArgs) -> anyhow::Result<()> {
+    let ssh_dir = match peers_args.key_dir {
+        Some(dir) => dir,
+        None => {
+            let distro_home =
+                my_home()?.ok_or_else(|| anyhow::anyhow!("home directory not found"))?;
+            distro_home.join(".ssh")
+        }
+    };
+    let peers_file = crate::peers::peers_file(&ssh_dir);
+    let peers = load_peers(&peers_file)?;
+
+    if peers.is_empty() && peers_args.endpoint_ids.is_empty() {
+        println!("No peers recorded in {}", peers_file.display());
+        println!("(peers are recorded by 'iroh-ssh server --peer-source-range')");
+        return Ok(());
+    }
+
+    if !peers.is_empty() {
+        println!("Peers recorded in {}:", peers_file.display());
+        for (endpoint_id, addr) in &peers {
+            println!("  {addr:<15}  {endpoint_id}");
+        }
+    }
+
+    if !peers_args.endpoint_ids.is_empty() {
         println!();
+        println!("Source addresses in {}:", peers_args.peer_source_range);
+        for endpoint_id in &peers_args.endpoint_ids {
+            let endpoint_id = EndpointId::from_str(endpoint_id)?;
+            println!(
+                "  {:<15}  {endpoint_id}",
+                peers_args
+                    .peer_source_range
+                    .addr_for(&endpoint_id)
+                    .to_string()
+            );
+        }
     }
 
     Ok(())
 }
 
 pub mod service {
-    use std::path::PathBuf;
-
-    use crate::{ServiceParams, api::abs_key_dir, install_service, uninstall_service};
-
-    pub async fn install(
-        ssh_port: u16,
-        key_dir: Option<PathBuf>,
-        relay_url: Vec<String>,
-        extra_relay_url: Vec<String>,
-    ) -> anyhow::Result<()> {
-        if install_service(ServiceParams {
-            ssh_port,
-            key_dir: abs_key_dir(key_dir),
-            relay_url,
-            extra_relay_url,
-        })
-        .await
-        .is_err()
-        {
+    use crate::{ServerOpts, install_service, service::absolute_paths, uninstall_service};
+
+    pub async fn install(opts: ServerOpts) -> anyhow::Result<()> {
+        if install_service(absolute_paths(opts)?).await.is_err() {
             anyhow::bail!("service install is only supported on linux and windows");
         }
         Ok(())
@@ -113,12 +359,53 @@ pub mod service {
 }
 
 pub async fn server_mode(server_args: ServerArgs, service: bool) -> anyhow::Result<()> {
+    let opts = &server_args.opts;
+    let ssh_host = opts.ssh_host.clone().unwrap_or_default();
+    let ssh_addr = SocketAddr::new(
+        ssh_host
+            .resolve(opts.ssh_port)
+            .await
+            .with_context(|| format!("failed to resolve --ssh-host {ssh_host}"))?,
+        opts.ssh_port,
+    );
+    if opts.peer_source_range.is_some() && ssh_addr.is_ipv6() {
+        bail!("--peer-source-range needs an IPv4 --ssh-host, {ssh_host} is {ssh_addr}");
+    }
     let mut iroh_ssh_builder = IrohSsh::builder()
         .accept_incoming(true)
-        .accept_port(server_args.ssh_port)
-        .key_dir(server_args.key_dir.clone())
-        .relay_urls(parse_relay_urls(&server_args.relay_url)?)
-        .extra_relay_urls(parse_relay_urls(&server_args.extra_relay_url)?);
+        .accept_host(ssh_addr.ip())
+        .accept_port(opts.ssh_port)
+        .wait_for_sshd(opts.wait_for_sshd.is_some())
+        .bench(opts.bench)
+        .bench_allow(opts.bench_allow.clone())
+        .key_dir(opts.key_dir.clone())
+        .local_only(opts.no_relay)
+        .discovery(opts.discovery.clone())
+        .path_policy(opts.path)
+        .relay_urls(parse_relay_urls(&opts.relay_url)?)
+        .extra_relay_urls(parse_relay_urls(&opts.extra_relay_url)?)
+        .targets(Targets::new(opts.target.clone(), opts.allow.clone())?);
+    iroh_ssh_builder = bind(iroh_ssh_builder, &opts.bind)?;
+    iroh_ssh_builder = quic(iroh_ssh_builder, &opts.quic);
+    if let Some(path) = &opts.relay_map {
+        iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
+    }
+    if let Some(range) = opts.peer_source_range {
+        iroh_ssh_builder = iroh_ssh_builder.source_range(range);
+    }
+    let advert = opts.advertise.then(|| {
+        LocalAdvert::new(
+            &opts
+                .name
+                .clone()
+                .or_else(|| whoami::hostname().ok())
+                .unwrap_or("iroh-ssh".to_string()),
+            &whoami::username().unwrap_or("UNKNOWN_USER".to_string()),
+        )
+    });
+    if let Some(advert) = &advert {
+        iroh_ssh_builder = iroh_ssh_builder.advertise_local(advert.clone());
+    }
     if server_args.persist {
         iroh_ssh_builder = iroh_ssh_builder.dot_ssh_integration(true, service);
     }
@@ -130,61 +417,475 @@ pub async fn server_mode(server_args: ServerArgs, service: bool) -> anyhow::Resu
         whoami::username().unwrap_or("UNKNOWN_USER".to_string()),
         iroh_ssh.endpoint_id()
     );
+    if opts.no_relay {
+        println!(
+            "  (local mode: no relays, found via mDNS on the local network, or dial directly)"
+        );
+        println!(
+            "\n  iroh-ssh {}@{}\n",
+            whoami::username().unwrap_or("UNKNOWN_USER".to_string()),
+            format_endpoint_addr(&iroh_ssh.endpoint_addr())
+        );
+    } else {
+        // give the endpoint a moment to pick a home relay so the ticket carries it
+        tokio::time::timeout(TICKET_ONLINE_TIMEOUT, iroh_ssh.endpoint().online())
+            .await
+            .ok();
+    }
+    println!("  or with its relay and current addresses, when discovery is blocked:");
+    println!(
+        "\n  iroh-ssh {}@{}\n",
+        whoami::username().unwrap_or("UNKNOWN_USER".to_string()),
+        iroh_ssh.ticket()
+    );
     if server_args.persist {
-        let ssh_dir = match server_args.key_dir {
-            Some(dir) => dir,
-            None => {
-                let distro_home =
-                    my_home()?.ok_or_else(|| anyhow::anyhow!("home directory not found"))?;
-                distro_home.join(".ssh")
-            }
-        };
+        let ssh_dir = key_ssh_dir(service, opts.key_dir.as_deref())?;
         println!("  (using persistent keys in {})", ssh_dir.display());
+        let ticket_file = crate::ticket::ticket_file(&ssh_dir);
+        println!(
+            "  (current ticket kept in {} for 'iroh-ssh info')",
+            ticket_file.display()
+        );
+        let publisher = iroh_ssh.clone();
+        tokio::spawn(async move { publisher.publish_ticket(ticket_file).await });
     } else {
         println!(
             "  warning: (using ephemeral keys, run 'iroh-ssh server --persist' to create persistent keys)"
         );
     }
     println!();
-    println!(
-        "client -> iroh-ssh -> direct connect -> iroh-ssh -> local ssh :{}",
-        server_args.ssh_port
-    );
+    match ssh_host {
+        SshHost::Name(name) => println!(
+            "client -> iroh-ssh -> direct connect -> iroh-ssh -> local ssh {ssh_addr} ({name})"
+        ),
+        SshHost::Ip(_) => {
+            println!("client -> iroh-ssh -> direct connect -> iroh-ssh -> local ssh {ssh_addr}")
+        }
+    }
+    if opts.wait_for_sshd.is_some() {
+        println!(
+            "local ssh {ssh_addr} is checked in the background, sessions are rejected while it's down"
+        );
+    }
+    if opts.bench {
+        if opts.bench_allow.is_empty() {
+            println!("answering 'iroh-ssh bench' from any peer");
+        } else {
+            println!(
+                "answering 'iroh-ssh bench' from {} allowed peer(s)",
+                opts.bench_allow.len()
+            );
+        }
+    }
+    let bound: Vec<String> = iroh_ssh
+        .endpoint()
+        .bound_sockets()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    println!("listening for peers on udp {}", bound.join(", "));
+    if let Some(advert) = &advert {
+        println!(
+            "advertised as '{}' to 'iroh-ssh discover' on the local network",
+            advert.name
+        );
+    }
+    if let Some(range) = opts.peer_source_range {
+        println!("peers dial sshd from per-peer source addresses in {range}");
+    }
+    for target in &opts.target {
+        println!(
+            "client --target-name {} -> iroh-ssh -> {}",
+            target.name, target.addr
+        );
+    }
 
     println!("Waiting for incoming connections...");
     println!("Press Ctrl+C to exit");
+    if let Some(secs) = opts.wait_for_sshd.filter(|secs| *secs > 0) {
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => return res.map_err(Into::into),
+            res = iroh_ssh.wait_for_sshd(Some(Duration::from_secs(secs))) => res?,
+        }
+    }
     tokio::signal::ctrl_c().await?;
     Ok(())
 }
 
 pub async fn proxy_mode(proxy_args: ProxyArgs) -> anyhow::Result<()> {
-    let iroh_ssh = IrohSsh::builder()
+    let endpoint_addr = resolve_target(&proxy_args.endpoint_id)?.map(|addr| {
+        addr.with_addrs(
+            proxy_args
+                .proxy
+                .addrs
+                .iter()
+                .copied()
+                .map(TransportAddr::Ip),
+        )
+    });
+
+    #[cfg(unix)]
+    if let Some(endpoint_addr) = &endpoint_addr
+        && use_agent(&proxy_args)
+    {
+        proxy_via_agent(endpoint_addr, &proxy_args).await?;
+    }
+
+    let mut iroh_ssh_builder = IrohSsh::builder()
         .accept_incoming(false)
+        .local_only(proxy_args.proxy.no_relay)
+        .discovery(proxy_args.proxy.discovery.clone())
+        .path_policy(proxy_args.proxy.path)
         .relay_urls(parse_relay_urls(&proxy_args.relay_url)?)
-        .extra_relay_urls(parse_relay_urls(&proxy_args.extra_relay_url)?)
-        .build()
-        .await?;
-    let hostname = proxy_args
-        .endpoint_id
-        .split(":")
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("failed to parse hostname"))?;
-    if hostname.len() == 64 && hostname.chars().all(|c| c.is_ascii_hexdigit()) {
-        let endpoint_id = EndpointId::from_str(hostname)?;
-        iroh_ssh.connect_pubkey(endpoint_id).await
+        .extra_relay_urls(parse_relay_urls(&proxy_args.extra_relay_url)?);
+    iroh_ssh_builder = bind(iroh_ssh_builder, &proxy_args.proxy.bind)?;
+    iroh_ssh_builder = quic(iroh_ssh_builder, &proxy_args.proxy.quic);
+    if let Some(path) = &proxy_args.proxy.relay_map {
+        iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
+    }
+    if let Some(cache) = default_addr_cache() {
+        iroh_ssh_builder = iroh_ssh_builder.addr_cache(cache);
+    }
+    let iroh_ssh = iroh_ssh_builder.build().await?;
+    if let Some(endpoint_addr) = endpoint_addr {
+        let res = iroh_ssh
+            .connect_pubkey(
+                endpoint_addr,
+                proxy_args.proxy.target_name.as_deref(),
+                &retry_policy(&proxy_args.proxy, proxy_args.verbose),
+            )
+            .await;
+        if let Err(err @ Error::TunnelClosed { .. }) = res {
+            // ssh itself only reports "Connection closed", so explain why on stderr
+            eprintln!("iroh-ssh: {err}");
+            std::process::exit(exit_code(&err.into()));
+        }
+        res?;
     } else {
         // fallback to dns base (or ip) HostName connection (no iroh)
-        iroh_ssh.connect_tcpip(&proxy_args.endpoint_id).await
+        iroh_ssh.connect_tcpip(&proxy_args.endpoint_id).await?;
+    }
+    Ok(())
+}
+
+/// Whether the proxy may hand off to a running agent: only when it would connect the way the
+/// agent's endpoint does, since the agent can't take over relay, discovery or transport flags.
+#[cfg(unix)]
+fn use_agent(proxy_args: &ProxyArgs) -> bool {
+    let proxy = &proxy_args.proxy;
+    !proxy_args.no_agent
+        && proxy_args.relay_url.is_empty()
+        && proxy_args.extra_relay_url.is_empty()
+        && !proxy.no_relay
+        && proxy.relay_map.is_none()
+        && proxy.discovery.is_empty()
+        && proxy.path == crate::PathPolicy::Any
+        && proxy.bind == BindOpts::default()
+        && proxy.quic == QuicOpts::default()
+}
+
+/// Tunnels stdin/stdout through a running agent and exits, returns if no agent is running.
+#[cfg(unix)]
+async fn proxy_via_agent(
+    endpoint_addr: &EndpointAddr,
+    proxy_args: &ProxyArgs,
+) -> anyhow::Result<()> {
+    use crate::agent::{self, AgentError, AgentRequest};
+
+    let Ok(socket) = agent::default_socket_path() else {
+        return Ok(());
+    };
+    let policy = retry_policy(&proxy_args.proxy, proxy_args.verbose);
+    let req = AgentRequest {
+        endpoint_id: endpoint_addr.id,
+        request: match &proxy_args.proxy.target_name {
+            Some(name) => TargetRequest::Named(name.clone()),
+            None => TargetRequest::Ssh,
+        },
+        connect_timeout: policy.connect_timeout,
+        retries: policy.retries,
+        addrs: endpoint_addr.addrs.iter().cloned().collect(),
+    };
+    match agent::connect(&socket, &req).await {
+        Ok(stream) => {
+            if proxy_args.verbose {
+                eprintln!("iroh-ssh: using agent at {}", socket.display());
+            }
+            match agent::run_tunnel(stream, tokio::io::stdin(), tokio::io::stdout()).await {
+                Ok(()) => std::process::exit(0),
+                Err(AgentError::Failed { exit_code, message }) => {
+                    // ssh itself only reports "Connection closed", so explain why on stderr
+                    eprintln!("iroh-ssh: {message}");
+                    std::process::exit(exit_code);
+                }
+                Err(AgentError::Unavailable(e)) => {
+                    eprintln!("iroh-ssh: lost the agent at {}: {e}", socket.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(AgentError::Failed { exit_code, message }) => {
+            eprintln!("iroh-ssh: {message}");
+            std::process::exit(exit_code);
+        }
+        Err(AgentError::Unavailable(e)) => {
+            if proxy_args.verbose {
+                eprintln!(
+                    "iroh-ssh: no agent at {} ({e}), connecting directly",
+                    socket.display()
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+pub async fn agent_mode(agent_args: AgentArgs) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut iroh_ssh_builder = IrohSsh::builder()
+            .accept_incoming(false)
+            .local_only(agent_args.no_relay)
+            .discovery(agent_args.discovery.clone())
+            .relay_urls(parse_relay_urls(&agent_args.relay_url)?)
+            .extra_relay_urls(parse_relay_urls(&agent_args.extra_relay_url)?);
+        iroh_ssh_builder = bind(iroh_ssh_builder, &agent_args.bind)?;
+        iroh_ssh_builder = quic(iroh_ssh_builder, &agent_args.quic);
+        if let Some(path) = &agent_args.relay_map {
+            iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
+        }
+        if let Some(cache) = default_addr_cache() {
+            iroh_ssh_builder = iroh_ssh_builder.addr_cache(cache);
+        }
+        let iroh_ssh = iroh_ssh_builder.build().await?;
+        let socket = match agent_args.socket {
+            Some(socket) => socket,
+            None => crate::agent::default_socket_path()?,
+        };
+
+        let listener = crate::Agent::listen(&socket)?;
+
+        println!("iroh-ssh agent listening on {}", socket.display());
+        println!(
+            "'iroh-ssh proxy' (and so 'iroh-ssh user@<ENDPOINT_ID>') now reuses its connections"
+        );
+        println!("Press Ctrl+C to exit");
+        let res = tokio::select! {
+            res = crate::Agent::new(iroh_ssh).serve(listener) => res.map_err(Into::into),
+            res = tokio::signal::ctrl_c() => res.map_err(Into::into),
+        };
+        std::fs::remove_file(&socket).ok();
+        res
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = agent_args;
+        bail!("the agent is only supported on unix");
+    }
+}
+
+pub async fn mux_mode(op: MuxCmd) -> anyhow::Result<()> {
+    match op {
+        MuxCmd::Stop(args) => {
+            #[cfg(unix)]
+            {
+                let control_path = crate::mux::control_path(args.target_name.as_deref())?;
+                let status = crate::mux::stop_command(
+                    &args.target,
+                    &control_path,
+                    args.port,
+                    args.login_user.as_deref(),
+                )
+                .status()
+                .await
+                .map_err(Error::SshSpawn)?;
+                if !status.success() {
+                    bail!("no shared tunnel to {} is running", args.target);
+                }
+                Ok(())
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = args;
+                bail!("--mux is only supported on unix");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "relay")]
+pub async fn relay_mode(relay_args: crate::cli::RelayArgs) -> anyhow::Result<()> {
+    crate::relay::run(relay_args).await
+}
+
+pub async fn relays_mode(op: RelaysCmd) -> anyhow::Result<()> {
+    let RelaysCmd::Check(check_args) = op;
+    let mut iroh_ssh_builder = IrohSsh::builder()
+        .accept_incoming(false)
+        .discovery(vec![DiscoveryBackend::None])
+        .relay_urls(parse_relay_urls(&check_args.relay_url)?)
+        .extra_relay_urls(parse_relay_urls(&check_args.extra_relay_url)?);
+    if let Some(path) = &check_args.relay_map {
+        iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
+    }
+    let relays = iroh_ssh_builder.relays();
+    let iroh_ssh = iroh_ssh_builder.build().await?;
+
+    println!("Probing {} relay(s)...", relays.len());
+    let check = iroh_ssh
+        .check_relays(&relays, Duration::from_secs(check_args.timeout))
+        .await;
+    println!();
+    println!("{:<48}  {:>11}  {:<4}", "RELAY", "LATENCY", "QUIC");
+    for relay in &check.relays {
+        let latency = match relay.latency {
+            Some(latency) => format!("{}ms", latency.as_millis()),
+            None => "unreachable".to_string(),
+        };
+        let quic = match relays
+            .get(&relay.url)
+            .and_then(|config| config.quic.clone())
+        {
+            None => "off".to_string(),
+            Some(_) if relay.quic => "ok".to_string(),
+            Some(quic) => format!("no answer on udp port {}", quic.port),
+        };
+        let home = if check.home_relay.as_ref() == Some(&relay.url) {
+            "  (home)"
+        } else {
+            ""
+        };
+        println!("{:<48}  {latency:>11}  {quic}{home}", relay.url.to_string());
+    }
+    println!();
+    match &check.home_relay {
+        Some(url) => println!("Home relay: {url}"),
+        None => {
+            println!("No home relay, none of the relays could be reached");
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+pub async fn bench_mode(bench_args: BenchArgs) -> anyhow::Result<()> {
+    let mut iroh_ssh_builder = IrohSsh::builder()
+        .accept_incoming(false)
+        .local_only(bench_args.no_relay)
+        .discovery(bench_args.discovery.clone())
+        .relay_urls(parse_relay_urls(&bench_args.relay_url)?)
+        .extra_relay_urls(parse_relay_urls(&bench_args.extra_relay_url)?);
+    iroh_ssh_builder = bind(iroh_ssh_builder, &bench_args.bind)?;
+    iroh_ssh_builder = quic(iroh_ssh_builder, &bench_args.quic);
+    if let Some(path) = &bench_args.relay_map {
+        iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
     }
+    let iroh_ssh = iroh_ssh_builder.build().await?;
+    let endpoint_addr = resolve_target(&bench_args.endpoint_id)?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "'{}' is not an endpoint id, ticket or alias",
+                bench_args.endpoint_id
+            )
+        })?
+        .with_addrs(bench_args.addrs.iter().copied().map(TransportAddr::Ip));
+    let endpoint_id = endpoint_addr.id;
+
+    eprintln!(
+        "Benchmarking {endpoint_id}: {} up and down, {} round trips, {} warm connects...",
+        bench_args.bytes, bench_args.pings, bench_args.connects
+    );
+    let options = BenchOptions {
+        bytes: bench_args.bytes.0,
+        pings: bench_args.pings,
+        warm_connects: bench_args.connects,
+    };
+    let report = iroh_ssh
+        .bench(endpoint_addr, &options)
+        .await
+        .with_context(|| {
+            format!("benchmark failed, is {endpoint_id} running 'iroh-ssh server --bench'?")
+        })?;
+
+    if bench_args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!();
+    match &report.path_addr {
+        Some(addr) => println!("{:<10}  {} {addr}", "path", report.path),
+        None => println!("{:<10}  {}", "path", report.path),
+    }
+    match report.warm_connect_ms {
+        Some(warm) => println!(
+            "{:<10}  cold {:.1}ms, warm {warm:.1}ms",
+            "connect", report.cold_connect_ms
+        ),
+        None => println!("{:<10}  cold {:.1}ms", "connect", report.cold_connect_ms),
+    }
+    let rtt = &report.rtt_ms;
+    println!(
+        "{:<10}  min {:.1}ms  p50 {:.1}ms  p90 {:.1}ms  max {:.1}ms",
+        "rtt", rtt.min, rtt.p50, rtt.p90, rtt.max
+    );
+    for (name, throughput) in [("upload", &report.upload), ("download", &report.download)] {
+        println!(
+            "{name:<10}  {:.1} Mbit/s  ({} in {:.2}s)",
+            throughput.mbit_per_sec,
+            ByteSize(throughput.bytes),
+            throughput.secs
+        );
+    }
+    Ok(())
+}
+
+pub async fn console_mode(console_args: ConsoleArgs) -> anyhow::Result<()> {
+    let mut iroh_ssh_builder = IrohSsh::builder()
+        .accept_incoming(false)
+        .local_only(console_args.no_relay)
+        .discovery(console_args.discovery.clone())
+        .relay_urls(parse_relay_urls(&console_args.relay_url)?)
+        .extra_relay_urls(parse_relay_urls(&console_args.extra_relay_url)?);
+    if let Some(path) = &console_args.relay_map {
+        iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
+    }
+    if let Some(cache) = default_addr_cache() {
+        iroh_ssh_builder = iroh_ssh_builder.addr_cache(cache);
+    }
+    let iroh_ssh = iroh_ssh_builder.build().await?;
+    let endpoint_addr = resolve_target(&console_args.endpoint_id)?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "'{}' is not an endpoint id or ticket",
+                console_args.endpoint_id
+            )
+        })?
+        .with_addrs(console_args.addrs.iter().copied().map(TransportAddr::Ip));
+
+    eprintln!(
+        "Attaching to '{}' on {}, press Ctrl-] to detach",
+        console_args.target_name, endpoint_addr.id
+    );
+    iroh_ssh
+        .console(endpoint_addr, &console_args.target_name)
+        .await?;
+    eprintln!("\r\nDetached from '{}'", console_args.target_name);
+    Ok(())
 }
 
 pub async fn client_mode(connect_args: ConnectArgs) -> anyhow::Result<()> {
-    let iroh_ssh = IrohSsh::builder()
+    let mut iroh_ssh_builder = IrohSsh::builder()
         .accept_incoming(false)
+        .local_only(connect_args.proxy.no_relay)
+        .discovery(connect_args.proxy.discovery.clone())
+        .path_policy(connect_args.proxy.path)
         .relay_urls(parse_relay_urls(&connect_args.relay_url)?)
-        .extra_relay_urls(parse_relay_urls(&connect_args.extra_relay_url)?)
-        .build()
-        .await?;
+        .extra_relay_urls(parse_relay_urls(&connect_args.extra_relay_url)?);
+    if let Some(path) = &connect_args.proxy.relay_map {
+        iroh_ssh_builder = iroh_ssh_builder.relay_map(load_relay_map(path)?);
+    }
+    // --bind is left to the ProxyCommand, binding it here too would take its port
+    let iroh_ssh = iroh_ssh_builder.build().await?;
     let mut ssh_process = match iroh_ssh
         .start_ssh(
             connect_args.target,
@@ -192,22 +893,21 @@ pub async fn client_mode(connect_args: ConnectArgs) -> anyhow::Result<()> {
             connect_args.remote_cmd,
             &connect_args.relay_url,
             &connect_args.extra_relay_url,
+            &connect_args.proxy,
         )
         .await
     {
         Ok(child) => child,
-        Err(err) => match err.kind() {
-            std::io::ErrorKind::NotFound => {
-                eprintln!(
-                    "SSH command not found, please make sure your system has an SSH client installed and and that the exact cmd \"ssh\" is in your PATH"
-                );
-                std::process::exit(1);
-            }
-            _ => {
-                eprintln!("Unknown failure when calling the SSH client: {err}");
-                std::process::exit(1);
-            }
-        },
+        Err(Error::SshSpawn(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!(
+                "SSH command not found, please make sure your system has an SSH client installed and and that the exact cmd \"ssh\" is in your PATH"
+            );
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Unknown failure when calling the SSH client: {err:#}");
+            std::process::exit(1);
+        }
     };
 
     let status = ssh_process.wait().await?;
@@ -254,3 +954,38 @@ pub(crate) fn abs_key_dir(key_dir: Option<PathBuf>) -> Option<PathBuf> {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Cmd};
+    use clap::Parser as _;
+
+    fn proxy_args(args: &[&str]) -> ProxyArgs {
+        let cli = Cli::try_parse_from(["iroh-ssh", "proxy", "host"].iter().chain(args)).unwrap();
+        match cli.cmd {
+            Some(Cmd::Proxy(proxy_args)) => proxy_args,
+            cmd => panic!("parsed {cmd:?}"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn proxy_uses_agent_only_with_default_connect_options() {
+        assert!(use_agent(&proxy_args(&[])));
+        assert!(use_agent(&proxy_args(&["--target-name", "nas", "-v"])));
+        for args in [
+            &["--no-agent"][..],
+            &["--no-relay"],
+            &["--relay-url", "https://relay.example.com"],
+            &["--extra-relay-url", "https://relay.example.com"],
+            &["--relay-map", "relays.toml"],
+            &["--discovery", "local"],
+            &["--path", "direct-only"],
+            &["--ipv4-only"],
+            &["--quic-congestion", "bbr"],
+        ] {
+            assert!(!use_agent(&proxy_args(args)), "{args:?}");
+        }
+    }
+}
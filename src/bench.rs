@@ -0,0 +1,386 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use iroh::{
+    EndpointAddr, Watcher as _,
+    endpoint::{Connection, ConnectionType, RecvStream, SendStream, VarInt},
+    protocol::{AcceptError, ProtocolHandler},
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinSet,
+};
+
+use crate::{
+    CloseCode, Error, IrohSsh,
+    pump::{PUMP_BUFFER_SIZE, pump},
+};
+
+/// Echoes the stream back, for timing round trips.
+const OP_ECHO: u8 = 0;
+/// Reads the stream to its end and answers with the byte count.
+const OP_UPLOAD: u8 = 1;
+/// Sends the requested number of bytes.
+const OP_DOWNLOAD: u8 = 2;
+
+/// Largest download a client can ask a bench server for in one stream.
+const MAX_DOWNLOAD: u64 = 4 << 30;
+
+/// Bytes per round trip, small enough to fit a single packet.
+const PING_SIZE: usize = 32;
+
+/// Benchmark streams served at once per connection, the client only ever opens one.
+const MAX_BENCH_STREAMS: usize = 2;
+
+/// Answers `iroh-ssh bench` on [`IrohSsh::BENCH_ALPN`], see [`crate::Builder::bench`].
+///
+/// Connections go through the server's session admission, so they are limited and
+/// closed on shutdown like tunnels.
+#[derive(Debug, Clone)]
+pub(crate) struct BenchServer {
+    iroh_ssh: IrohSsh,
+}
+
+impl BenchServer {
+    pub(crate) fn new(iroh_ssh: IrohSsh) -> Self {
+        Self { iroh_ssh }
+    }
+}
+
+impl ProtocolHandler for BenchServer {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        let endpoint_id = connection.remote_id()?;
+        let allow = &self.iroh_ssh.bench_allow;
+        if !allow.is_empty() && !allow.contains(&endpoint_id) {
+            println!("Rejecting benchmark from {endpoint_id}: not in --bench-allow");
+            CloseCode::NotAuthorized.close(&connection);
+            return Ok(());
+        }
+        if let Err(code) = self.iroh_ssh.admit(&connection, endpoint_id) {
+            println!("Rejecting benchmark from {endpoint_id}: {}", code.reason());
+            code.close(&connection);
+            return Ok(());
+        }
+
+        println!("Benchmark from {endpoint_id}");
+        let mut streams = JoinSet::new();
+        while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+            while streams.try_join_next().is_some() {}
+            let slot = if streams.len() >= MAX_BENCH_STREAMS {
+                Err(CloseCode::RateLimited)
+            } else {
+                self.iroh_ssh.open_stream(endpoint_id)
+            };
+            let slot = match slot {
+                Ok(slot) => slot,
+                Err(code) => {
+                    code.reset(&mut send, &mut recv);
+                    continue;
+                }
+            };
+            streams.spawn(async move {
+                let _slot = slot;
+                if let Err(e) = serve(send, recv).await {
+                    tracing::debug!("benchmark stream from {endpoint_id} failed: {e}");
+                }
+            });
+        }
+        streams.join_all().await;
+        self.iroh_ssh.release(&connection);
+        Ok(())
+    }
+}
+
+async fn serve(mut send: SendStream, mut recv: RecvStream) -> io::Result<()> {
+    let op = recv.read_u8().await?;
+    let arg = recv.read_u64().await?;
+    match op {
+        OP_ECHO => {
+            pump(&mut recv, &mut send).await?;
+        }
+        OP_UPLOAD => {
+            let received = pump(&mut recv, &mut tokio::io::sink()).await?;
+            send.write_u64(received).await?;
+        }
+        OP_DOWNLOAD => send_zeros(&mut send, arg.min(MAX_DOWNLOAD)).await?,
+        op => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown benchmark op {op}"),
+            ));
+        }
+    }
+    send.finish().map_err(io::Error::other)?;
+    send.stopped().await.ok();
+    Ok(())
+}
+
+async fn send_zeros(send: &mut SendStream, mut len: u64) -> io::Result<()> {
+    let buf = vec![0; PUMP_BUFFER_SIZE];
+    while len > 0 {
+        let n = len.min(buf.len() as u64) as usize;
+        send.write_all(&buf[..n]).await?;
+        len -= n as u64;
+    }
+    Ok(())
+}
+
+/// What [`IrohSsh::bench`] measures.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchOptions {
+    /// Bytes to upload, and again to download.
+    pub bytes: u64,
+    /// Round trips to time.
+    pub pings: usize,
+    /// Connects to time after the first, cold one.
+    pub warm_connects: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            bytes: 64 << 20,
+            pings: 20,
+            warm_connects: 3,
+        }
+    }
+}
+
+/// The results of [`IrohSsh::bench`], times in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub endpoint_id: String,
+    /// The path the transfers ended on: `direct`, `relay`, `mixed` or `none`.
+    pub path: &'static str,
+    /// The peer's address or relay URL on that path.
+    pub path_addr: Option<String>,
+    /// The first connect, including discovery and hole punching.
+    pub cold_connect_ms: f64,
+    /// Median of the connects after the first, `None` if none were made.
+    pub warm_connect_ms: Option<f64>,
+    pub rtt_ms: RttStats,
+    pub upload: Throughput,
+    pub download: Throughput,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RttStats {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub max: f64,
+}
+
+impl RttStats {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+        let at = |q: f64| millis(samples[((samples.len() - 1) as f64 * q).round() as usize]);
+        Self {
+            min: at(0.0),
+            p50: at(0.5),
+            p90: at(0.9),
+            max: at(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub secs: f64,
+    pub mbit_per_sec: f64,
+}
+
+impl Throughput {
+    fn new(bytes: u64, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        Self {
+            bytes,
+            secs,
+            mbit_per_sec: if secs > 0.0 {
+                bytes as f64 * 8.0 / secs / 1e6
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl IrohSsh {
+    /// ALPN of the benchmark server `iroh-ssh bench` talks to.
+    #[allow(non_snake_case)]
+    pub fn BENCH_ALPN() -> Vec<u8> {
+        b"/iroh/ssh/bench".to_vec()
+    }
+
+    /// Measures connect times, round trips and throughput to a server built with
+    /// [`crate::Builder::bench`].
+    ///
+    /// The address cache and path policy are left out so the first connect is a cold one.
+    pub async fn bench(
+        &self,
+        endpoint_addr: impl Into<EndpointAddr>,
+        options: &BenchOptions,
+    ) -> Result<BenchReport, Error> {
+        let endpoint_addr = endpoint_addr.into();
+        let endpoint_id = endpoint_addr.id;
+
+        let started = Instant::now();
+        let conn = self.connect_bench(endpoint_addr.clone()).await?;
+        let cold_connect = started.elapsed();
+
+        let rtt = ping(&conn, options.pings).await?;
+        let upload = upload(&conn, options.bytes).await?;
+        let download = download(&conn, options.bytes).await?;
+        let path = self
+            .endpoint
+            .conn_type(endpoint_id)
+            .map(|mut path| path.get())
+            .unwrap_or(ConnectionType::None);
+        conn.close(VarInt::from_u32(0), b"done");
+
+        let mut warm = Vec::new();
+        for _ in 0..options.warm_connects {
+            let started = Instant::now();
+            let conn = self.connect_bench(endpoint_addr.clone()).await?;
+            warm.push(started.elapsed());
+            conn.close(VarInt::from_u32(0), b"done");
+        }
+        warm.sort();
+
+        let (path, path_addr) = match path {
+            ConnectionType::Direct(addr) => ("direct", Some(addr.to_string())),
+            ConnectionType::Relay(url) => ("relay", Some(url.to_string())),
+            ConnectionType::Mixed(addr, _) => ("mixed", Some(addr.to_string())),
+            ConnectionType::None => ("none", None),
+        };
+        Ok(BenchReport {
+            endpoint_id: endpoint_id.to_string(),
+            path,
+            path_addr,
+            cold_connect_ms: millis(cold_connect),
+            warm_connect_ms: warm.get(warm.len() / 2).copied().map(millis),
+            rtt_ms: RttStats::new(rtt),
+            upload,
+            download,
+        })
+    }
+
+    async fn connect_bench(&self, endpoint_addr: EndpointAddr) -> Result<Connection, Error> {
+        let endpoint_id = endpoint_addr.id;
+        self.endpoint
+            .connect(endpoint_addr, &IrohSsh::BENCH_ALPN())
+            .await
+            .map_err(|e| Error::from_connect(endpoint_id, e))
+    }
+}
+
+async fn open(conn: &Connection, op: u8, arg: u64) -> Result<(SendStream, RecvStream), Error> {
+    let endpoint_id = conn.remote_id().map_err(io::Error::other)?;
+    let (mut send, recv) = conn
+        .open_bi()
+        .await
+        .map_err(|e| Error::from_connection(endpoint_id, e))?;
+    send.write_u8(op).await?;
+    send.write_u64(arg).await?;
+    Ok((send, recv))
+}
+
+async fn ping(conn: &Connection, pings: usize) -> Result<Vec<Duration>, Error> {
+    let (mut send, mut recv) = open(conn, OP_ECHO, 0).await?;
+    let mut buf = [0; PING_SIZE];
+    let mut samples = Vec::with_capacity(pings);
+    for _ in 0..pings {
+        let started = Instant::now();
+        AsyncWriteExt::write_all(&mut send, &buf).await?;
+        AsyncReadExt::read_exact(&mut recv, &mut buf).await?;
+        samples.push(started.elapsed());
+    }
+    send.finish().map_err(io::Error::other)?;
+    Ok(samples)
+}
+
+/// Timed until the server confirms it read everything.
+async fn upload(conn: &Connection, bytes: u64) -> Result<Throughput, Error> {
+    let started = Instant::now();
+    let (mut send, mut recv) = open(conn, OP_UPLOAD, 0).await?;
+    send_zeros(&mut send, bytes).await?;
+    send.finish().map_err(io::Error::other)?;
+    let received = recv.read_u64().await?;
+    Ok(Throughput::new(received, started.elapsed()))
+}
+
+/// Timed from the request to the last byte, one round trip more than the transfer itself.
+async fn download(conn: &Connection, bytes: u64) -> Result<Throughput, Error> {
+    let started = Instant::now();
+    let (mut send, mut recv) = open(conn, OP_DOWNLOAD, bytes).await?;
+    send.finish().map_err(io::Error::other)?;
+    let received = pump(&mut recv, &mut tokio::io::sink()).await?;
+    Ok(Throughput::new(received, started.elapsed()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::{Endpoint, RelayMode, protocol::Router};
+
+    #[test]
+    fn rtt_stats_pick_percentiles() {
+        let samples = (1..=10).map(Duration::from_millis).collect();
+        let stats = RttStats::new(samples);
+        assert_eq!((stats.min, stats.max), (1.0, 10.0));
+        assert!((5.0..=6.0).contains(&stats.p50));
+        assert_eq!(stats.p90, 9.0);
+        assert_eq!(RttStats::new(Vec::new()).max, 0.0);
+    }
+
+    async fn local_client() -> IrohSsh {
+        IrohSsh::builder()
+            .accept_incoming(false)
+            .local_only(true)
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn bench_measures_a_local_server() {
+        let client = local_client().await;
+        let stranger = local_client().await;
+        let server = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let iroh_ssh = IrohSsh::builder()
+            .bench(true)
+            .bench_allow(vec![client.endpoint().id()])
+            .build_on(server.clone())
+            .await
+            .unwrap();
+        let router = iroh_ssh.accept_on(Router::builder(server.clone())).spawn();
+
+        let options = BenchOptions {
+            bytes: 1 << 20,
+            pings: 5,
+            warm_connects: 1,
+        };
+        assert!(stranger.bench(server.addr(), &options).await.is_err());
+        let report = client.bench(server.addr(), &options).await.unwrap();
+        assert_eq!(report.upload.bytes, 1 << 20);
+        assert_eq!(report.download.bytes, 1 << 20);
+        assert!(report.rtt_ms.max >= report.rtt_ms.min);
+        assert!(report.warm_connect_ms.is_some());
+        assert_eq!(report.path, "direct");
+        router.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    str::FromStr,
+};
+
+/// A `--bind` value: a socket address, or a port for both IPv4 and IPv6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindAddr {
+    Port(u16),
+    Addr(SocketAddr),
+}
+
+impl BindAddr {
+    /// The socket addresses to bind, the unspecified address of each family for a bare port.
+    pub fn socket_addrs(self) -> Vec<SocketAddr> {
+        match self {
+            BindAddr::Port(port) => vec![
+                (Ipv4Addr::UNSPECIFIED, port).into(),
+                (Ipv6Addr::UNSPECIFIED, port).into(),
+            ],
+            BindAddr::Addr(addr) => vec![addr],
+        }
+    }
+}
+
+impl FromStr for BindAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(port) = s.parse() {
+            return Ok(BindAddr::Port(port));
+        }
+        match s.parse() {
+            Ok(addr) => Ok(BindAddr::Addr(addr)),
+            Err(_) => anyhow::bail!(
+                "invalid bind address '{s}', expected a port, IPV4:PORT or [IPV6]:PORT"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddr::Port(port) => write!(f, "{port}"),
+            BindAddr::Addr(addr) => write!(f, "{addr}"),
+        }
+    }
+}
+
+/// Which IP families the endpoint talks to peers over, see `--ipv4-only` and `--ipv6-only`.
+///
+/// iroh always opens an IPv4 socket, so the family that's turned off is
+/// bound to loopback only instead of not at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpFamily {
+    #[default]
+    Both,
+    V4Only,
+    V6Only,
+}
+
+/// Where the endpoint's UDP sockets listen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct BindConfig {
+    pub(crate) v4: Option<SocketAddrV4>,
+    pub(crate) v6: Option<SocketAddrV6>,
+    pub(crate) family: IpFamily,
+    /// Binds both families to loopback, so no direct path to another host can
+    /// form and everything goes through the relay. Overrides `v4` and `v6`.
+    pub(crate) loopback_only: bool,
+}
+
+impl BindConfig {
+    pub(crate) fn v4_addr(&self) -> SocketAddrV4 {
+        match self.family {
+            _ if self.loopback_only => SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            IpFamily::V6Only => SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0),
+            _ => self
+                .v4
+                .unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+        }
+    }
+
+    /// `None` keeps iroh's default, the port after the IPv4 one.
+    pub(crate) fn v6_addr(&self) -> Option<SocketAddrV6> {
+        match self.family {
+            _ if self.loopback_only => Some(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0)),
+            IpFamily::V4Only => Some(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0)),
+            _ => self.v6,
+        }
+    }
+
+    /// The requested address iroh didn't bind, it silently falls back to a
+    /// random port when the requested one is taken.
+    pub(crate) fn missing(&self, bound: &[SocketAddr]) -> Option<SocketAddr> {
+        if self.loopback_only {
+            return None;
+        }
+        let requested = [
+            self.v4
+                .filter(|_| self.family != IpFamily::V6Only)
+                .map(SocketAddr::V4),
+            self.v6
+                .filter(|_| self.family != IpFamily::V4Only)
+                .map(SocketAddr::V6),
+        ];
+        if let Some(addr) = requested.into_iter().flatten().find(|addr| {
+            !bound.iter().any(|bound| {
+                bound.ip() == addr.ip() && (addr.port() == 0 || bound.port() == addr.port())
+            })
+        }) {
+            return Some(addr);
+        }
+        // without an IPv6 socket there'd be nothing left to talk to peers with
+        if self.family == IpFamily::V6Only && !bound.iter().any(SocketAddr::is_ipv6) {
+            return Some((Ipv6Addr::UNSPECIFIED, 0).into());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_addrs_parse_and_check() {
+        for text in ["41641", "0.0.0.0:41641", "[::]:41641", "[::1]:0"] {
+            assert_eq!(BindAddr::from_str(text).unwrap().to_string(), text);
+        }
+        assert!(BindAddr::from_str("0.0.0.0").is_err());
+        assert_eq!(
+            BindAddr::from_str("41641").unwrap().socket_addrs(),
+            vec![
+                "0.0.0.0:41641".parse::<SocketAddr>().unwrap(),
+                "[::]:41641".parse().unwrap()
+            ]
+        );
+
+        let config = BindConfig {
+            v4: Some("0.0.0.0:41641".parse().unwrap()),
+            ..Default::default()
+        };
+        let bound: SocketAddr = "0.0.0.0:41641".parse().unwrap();
+        let fallback: SocketAddr = "0.0.0.0:50000".parse().unwrap();
+        assert_eq!(config.missing(&[bound]), None);
+        assert_eq!(config.missing(&[fallback]), Some(bound));
+
+        let v4_only = BindConfig {
+            family: IpFamily::V4Only,
+            ..config
+        };
+        assert_eq!(v4_only.v6_addr(), Some("[::1]:0".parse().unwrap()));
+        let v6_only = BindConfig {
+            family: IpFamily::V6Only,
+            ..Default::default()
+        };
+        assert_eq!(v6_only.v4_addr(), "127.0.0.1:0".parse().unwrap());
+        assert!(
+            v6_only
+                .missing(&["127.0.0.1:1234".parse().unwrap()])
+                .is_some()
+        );
+
+        let loopback = BindConfig {
+            loopback_only: true,
+            ..config
+        };
+        assert_eq!(loopback.v4_addr(), "127.0.0.1:0".parse().unwrap());
+        assert_eq!(loopback.v6_addr(), Some("[::1]:0".parse().unwrap()));
+        assert_eq!(loopback.missing(&[fallback]), None);
+    }
+}
@@ -1,11 +1,54 @@
-use std::{ffi::OsString, path::PathBuf};
+use std::{ffi::OsString, net::SocketAddr, path::PathBuf};
 
 use clap::{ArgAction, Args, Parser, Subcommand};
 
-const TARGET_HELP: &str = "Target in the form user@ENDPOINT_ID";
+use crate::{
+    AllowRule, BindAddr, ByteSize, CongestionControl, DiscoveryBackend, PathPolicy, SourceRange,
+    SshHost, TargetSpec,
+};
+
+const TARGET_HELP: &str = "Target in the form user@ENDPOINT_ID or user@TICKET";
 const RELAY_URL_HELP: &str = "Use only these relay servers, replacing the defaults (repeatable)";
 const EXTRA_RELAY_URL_HELP: &str = "Add relay servers alongside the defaults (repeatable)";
+const RELAY_MAP_HELP: &str = "Use the relays in this TOML file, with per-relay options like the QUIC port (see CUSTOM_RELAY.md)";
+const NO_RELAY_HELP: &str = "Don't use relays or internet discovery, only reach peers on the local network or at given addresses";
+const DISCOVERY_HELP: &str = "Find peers with dns, local (mDNS), a pkarr relay URL or none instead of the defaults (repeatable)";
+const PATH_HELP: &str = "Close connections that aren't direct (direct-only, disables relays) or that leave the relay (relay-only, keeps UDP on loopback)";
+const BIND_HELP: &str = "Listen for peers on this UDP port, IPV4:PORT or [IPV6]:PORT instead of a random port (repeatable)";
+const IPV4_ONLY_HELP: &str = "Only talk to peers over IPv4";
+const IPV6_ONLY_HELP: &str = "Only talk to peers over IPv6";
+const QUIC_STREAM_WINDOW_HELP: &str = "Bytes a peer may send per tunnel before they're read, e.g. 16M, raise it on long fast links (default: 1.25M)";
+const QUIC_CONN_WINDOW_HELP: &str = "Bytes a peer may send over all tunnels of a connection before they're read (default: unlimited)";
+const QUIC_SEND_WINDOW_HELP: &str =
+    "Bytes buffered for sending per connection, raise it with the peer's windows (default: 10M)";
+const QUIC_CONGESTION_HELP: &str = "Congestion controller: cubic, new-reno or bbr (default: cubic)";
+const QUIC_KEEP_ALIVE_HELP: &str =
+    "Ping idle connections every SECS seconds, 0 turns keep-alives off (default: 1)";
+const QUIC_IDLE_TIMEOUT_HELP: &str =
+    "Drop connections the peer hasn't answered for SECS seconds, 0 never does (default: 30)";
+const ADDR_HELP: &str =
+    "Also try this direct address of the server, e.g. when discovery is blocked (repeatable)";
+const TARGET_NAME_HELP: &str = "Connect to this named target on the server instead of its sshd";
+const CONNECT_TIMEOUT_HELP: &str = "Give up on a connection attempt after this many seconds";
+const RETRIES_HELP: &str = "Retry an unreachable host this many times with exponential backoff";
+const WAIT_FOR_HOST_HELP: &str = "Keep retrying until the host comes online, e.g. while it reboots";
+const MUX_HELP: &str = "Share one tunnel between ssh, scp, rsync and git calls to the same target (ssh ControlMaster, unix only)";
+const MUX_PERSIST_HELP: &str =
+    "Keep an idle shared tunnel open this long, in ssh's ControlPersist format (default 10m)";
 const KEY_DIR_HELP: &str = "Directory for iroh-ssh identity keys (default: ~/.ssh)";
+const SSH_HOST_HELP: &str = "Dial sshd on this IP, [IPV6] or hostname, a hostname is resolved once at startup (default: 127.0.0.1)";
+const WAIT_FOR_SSHD_HELP: &str = "Start even when sshd is down and reject sessions until it answers, exit after SECS if it never does (default: wait forever)";
+const BENCH_HELP: &str = "Answer 'iroh-ssh bench' from any peer or those in --bench-allow, it uploads and downloads as much as it asks for";
+const BENCH_ALLOW_HELP: &str =
+    "Only answer 'iroh-ssh bench' from this peer (repeatable, default: any peer)";
+const PEER_SOURCE_RANGE_HELP: &str = "Dial sshd from a per-peer source address in this IPv4 range (default 127.0.0.0/8, other platforms than linux need the addresses on loopback)";
+const TARGET_SPEC_HELP: &str =
+    "Expose HOST:PORT, unix:PATH or serial:DEVICE[@BAUD] as a named target (repeatable)";
+const ALLOW_RULE_HELP: &str =
+    "Allow a peer, or every peer with '*', to use a named target (repeatable)";
+const ADVERTISE_HELP: &str =
+    "Announce this server via mDNS so 'iroh-ssh discover' on the local network lists it";
+const ADVERTISE_NAME_HELP: &str = "Name to advertise (default: hostname)";
 
 #[derive(Parser, Debug)]
 #[command(name = "iroh-ssh", about = "ssh without ip")]
@@ -25,6 +68,9 @@ pub struct Cli {
     #[command(flatten)]
     pub ssh: SshOpts,
 
+    #[command(flatten)]
+    pub proxy: ProxyOpts,
+
     #[arg(trailing_var_arg = true)]
     pub remote_cmd: Option<Vec<OsString>>,
 }
@@ -40,10 +86,25 @@ pub enum Cmd {
         op: ServiceCmd,
     },
     Info(InfoArgs),
+    Peers(PeersArgs),
+    Discover(DiscoverArgs),
+    Console(ConsoleArgs),
+    Agent(AgentArgs),
+    Mux {
+        #[command(subcommand)]
+        op: MuxCmd,
+    },
+    #[cfg(feature = "relay")]
+    Relay(RelayArgs),
+    Relays {
+        #[command(subcommand)]
+        op: RelaysCmd,
+    },
+    Bench(BenchArgs),
     #[command(hide = true)]
     Proxy(ProxyArgs),
     #[command(hide = true)]
-    RunService(ServiceArgs),
+    RunService(ServerOpts),
     Version,
 }
 
@@ -57,6 +118,15 @@ pub struct ProxyArgs {
 
     #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
     pub extra_relay_url: Vec<String>,
+
+    #[command(flatten)]
+    pub proxy: ProxyOpts,
+
+    #[arg(short, long, help = "Print connection progress to stderr")]
+    pub verbose: bool,
+
+    #[arg(long, help = "Don't use a running 'iroh-ssh agent'")]
+    pub no_agent: bool,
 }
 
 #[derive(Args, Clone, Debug)]
@@ -73,6 +143,9 @@ pub struct ConnectArgs {
     #[command(flatten)]
     pub ssh: SshOpts,
 
+    #[command(flatten)]
+    pub proxy: ProxyOpts,
+
     #[arg(trailing_var_arg = true)]
     pub remote_cmd: Vec<OsString>,
 }
@@ -91,6 +164,9 @@ pub struct ExecArgs {
     #[command(flatten)]
     pub ssh: SshOpts,
 
+    #[command(flatten)]
+    pub proxy: ProxyOpts,
+
     #[arg(trailing_var_arg = true, required = true)]
     pub remote_cmd: Vec<OsString>,
 }
@@ -159,15 +235,290 @@ pub struct SshOpts {
 
     #[arg(short = 'q', help = "Quiet mode", action = ArgAction::SetTrue)]
     pub quiet: bool,
+
+    #[arg(long, env = "IROH_SSH_MUX", help = MUX_HELP)]
+    pub mux: bool,
+
+    #[arg(long, value_name = "PERSIST", env = "IROH_SSH_MUX_PERSIST", help = MUX_PERSIST_HELP)]
+    pub mux_persist: Option<String>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum MuxCmd {
+    /// Close the shared tunnel to a target started with --mux
+    Stop(MuxStopArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct MuxStopArgs {
+    #[arg(help = TARGET_HELP)]
+    pub target: String,
+
+    #[arg(long, value_name = "NAME", help = TARGET_NAME_HELP)]
+    pub target_name: Option<String>,
+
+    #[arg(
+        short = 'p',
+        long,
+        value_name = "PORT",
+        help = "Remote sshd port the tunnel was opened with"
+    )]
+    pub port: Option<u16>,
+
+    #[arg(
+        short = 'l',
+        value_name = "USER",
+        help = "Login user the tunnel was opened with"
+    )]
+    pub login_user: Option<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ConsoleArgs {
+    #[arg(help = "Endpoint ID or ticket of the iroh-ssh server")]
+    pub endpoint_id: String,
+
+    #[arg(long = "addr", value_name = "IP:PORT", help = ADDR_HELP, action = ArgAction::Append)]
+    pub addrs: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "console",
+        help = "Unix socket or serial target on the server to attach to"
+    )]
+    pub target_name: String,
+
+    #[arg(long, value_name = "URL", help = RELAY_URL_HELP, action = ArgAction::Append)]
+    pub relay_url: Vec<String>,
+
+    #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
+    pub extra_relay_url: Vec<String>,
+
+    #[arg(long, help = NO_RELAY_HELP, conflicts_with_all = ["relay_url", "extra_relay_url"])]
+    pub no_relay: bool,
+
+    #[arg(long, value_name = "FILE", help = RELAY_MAP_HELP, conflicts_with_all = ["relay_url", "extra_relay_url", "no_relay"])]
+    pub relay_map: Option<PathBuf>,
+
+    #[arg(long, value_name = "BACKEND", help = DISCOVERY_HELP, action = ArgAction::Append)]
+    pub discovery: Vec<DiscoveryBackend>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct AgentArgs {
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Listen on this socket (default: $XDG_RUNTIME_DIR/iroh-ssh/agent.sock)"
+    )]
+    pub socket: Option<PathBuf>,
+
+    #[arg(long, value_name = "URL", help = RELAY_URL_HELP, action = ArgAction::Append)]
+    pub relay_url: Vec<String>,
+
+    #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
+    pub extra_relay_url: Vec<String>,
+
+    #[arg(long, help = NO_RELAY_HELP, conflicts_with_all = ["relay_url", "extra_relay_url"])]
+    pub no_relay: bool,
+
+    #[arg(long, value_name = "FILE", help = RELAY_MAP_HELP, conflicts_with_all = ["relay_url", "extra_relay_url", "no_relay"])]
+    pub relay_map: Option<PathBuf>,
+
+    #[arg(long, value_name = "BACKEND", help = DISCOVERY_HELP, action = ArgAction::Append)]
+    pub discovery: Vec<DiscoveryBackend>,
+
+    #[command(flatten)]
+    pub bind: BindOpts,
+
+    #[command(flatten)]
+    pub quic: QuicOpts,
+}
+
+/// Runs an iroh relay server, see `CUSTOM_RELAY.md`.
+#[cfg(feature = "relay")]
+#[derive(Args, Clone, Debug)]
+pub struct RelayArgs {
+    #[arg(
+        long,
+        conflicts_with_all = ["tls_cert", "tls_key"],
+        help = "Serve plain http on port 3340 for local testing, no TLS"
+    )]
+    pub dev: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "tls_key",
+        help = "PEM certificate chain for https"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "tls_cert",
+        help = "PEM private key for https"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "IP:PORT",
+        help = "Serve http here (default: [::]:80, [::]:3340 with --dev)"
+    )]
+    pub http_bind: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "IP:PORT",
+        help = "Serve https here (default: [::]:443)"
+    )]
+    pub https_bind: Option<SocketAddr>,
+
+    #[arg(long, value_name = "ENDPOINT_ID", help = "Only relay for these endpoints (repeatable, default: everyone)", action = ArgAction::Append)]
+    pub allow: Vec<iroh::EndpointId>,
+}
+
+/// Client options that are passed on to `iroh-ssh proxy` through ssh's ProxyCommand.
+#[derive(Args, Clone, Default, Debug)]
+pub struct ProxyOpts {
+    #[arg(long, value_name = "NAME", help = TARGET_NAME_HELP)]
+    pub target_name: Option<String>,
+
+    #[arg(long, value_name = "SECS", help = CONNECT_TIMEOUT_HELP)]
+    pub connect_timeout: Option<u64>,
+
+    #[arg(long, value_name = "N", default_value_t = 0, help = RETRIES_HELP)]
+    pub retries: u32,
+
+    #[arg(long, help = WAIT_FOR_HOST_HELP)]
+    pub wait_for_host: bool,
+
+    #[arg(long, help = NO_RELAY_HELP, conflicts_with_all = ["relay_url", "extra_relay_url"])]
+    pub no_relay: bool,
+
+    #[arg(long, value_name = "FILE", help = RELAY_MAP_HELP, conflicts_with_all = ["relay_url", "extra_relay_url", "no_relay"])]
+    pub relay_map: Option<PathBuf>,
+
+    #[arg(long, value_name = "BACKEND", help = DISCOVERY_HELP, action = ArgAction::Append)]
+    pub discovery: Vec<DiscoveryBackend>,
+
+    #[arg(long, value_name = "POLICY", default_value_t = PathPolicy::Any, help = PATH_HELP)]
+    pub path: PathPolicy,
+
+    #[command(flatten)]
+    pub bind: BindOpts,
+
+    #[command(flatten)]
+    pub quic: QuicOpts,
+
+    #[arg(long = "addr", value_name = "IP:PORT", help = ADDR_HELP, action = ArgAction::Append)]
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// Where the endpoint listens for peers, see [`crate::Builder::bind_addr`].
+#[derive(Args, Clone, Default, Debug, PartialEq, Eq)]
+pub struct BindOpts {
+    #[arg(long = "bind", value_name = "ADDR", help = BIND_HELP, action = ArgAction::Append)]
+    pub bind_addrs: Vec<BindAddr>,
+
+    #[arg(long, help = IPV4_ONLY_HELP, conflicts_with = "ipv6_only")]
+    pub ipv4_only: bool,
+
+    #[arg(long, help = IPV6_ONLY_HELP)]
+    pub ipv6_only: bool,
+}
+
+/// QUIC transport tuning, see [`crate::Builder::stream_receive_window`].
+///
+/// Both ends need larger windows for fast transfers in both directions.
+#[derive(Args, Clone, Default, Debug, PartialEq, Eq)]
+pub struct QuicOpts {
+    #[arg(long, value_name = "SIZE", help = QUIC_STREAM_WINDOW_HELP)]
+    pub quic_stream_window: Option<ByteSize>,
+
+    #[arg(long, value_name = "SIZE", help = QUIC_CONN_WINDOW_HELP)]
+    pub quic_conn_window: Option<ByteSize>,
+
+    #[arg(long, value_name = "SIZE", help = QUIC_SEND_WINDOW_HELP)]
+    pub quic_send_window: Option<ByteSize>,
+
+    #[arg(long, value_name = "ALGO", help = QUIC_CONGESTION_HELP)]
+    pub quic_congestion: Option<CongestionControl>,
+
+    #[arg(long, value_name = "SECS", help = QUIC_KEEP_ALIVE_HELP)]
+    pub quic_keep_alive: Option<u64>,
+
+    #[arg(long, value_name = "SECS", help = QUIC_IDLE_TIMEOUT_HELP)]
+    pub quic_idle_timeout: Option<u64>,
+}
+
+impl QuicOpts {
+    /// The options as command line arguments, for the ProxyCommand and services.
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(format!("--{name}"));
+                args.push(value);
+            }
+        };
+        push(
+            "quic-stream-window",
+            self.quic_stream_window.map(|s| s.to_string()),
+        );
+        push(
+            "quic-conn-window",
+            self.quic_conn_window.map(|s| s.to_string()),
+        );
+        push(
+            "quic-send-window",
+            self.quic_send_window.map(|s| s.to_string()),
+        );
+        push(
+            "quic-congestion",
+            self.quic_congestion.map(|c| c.to_string()),
+        );
+        push(
+            "quic-keep-alive",
+            self.quic_keep_alive.map(|s| s.to_string()),
+        );
+        push(
+            "quic-idle-timeout",
+            self.quic_idle_timeout.map(|s| s.to_string()),
+        );
+        args
+    }
 }
 
 #[derive(Args, Clone, Debug)]
 pub struct ServerArgs {
+    #[arg(short, long, default_value_t = false)]
+    pub persist: bool,
+
+    #[command(flatten)]
+    pub opts: ServerOpts,
+}
+
+/// What a server runs with, shared by `server`, `service install` and the service itself.
+#[derive(Args, Clone, Debug, PartialEq, Eq)]
+pub struct ServerOpts {
     #[arg(long, default_value = "22")]
     pub ssh_port: u16,
 
-    #[arg(short, long, default_value_t = false)]
-    pub persist: bool,
+    #[arg(long, value_name = "HOST", help = SSH_HOST_HELP)]
+    pub ssh_host: Option<SshHost>,
+
+    #[arg(long, value_name = "SECS", num_args = 0..=1, require_equals = true, default_missing_value = "0", help = WAIT_FOR_SSHD_HELP)]
+    pub wait_for_sshd: Option<u64>,
+
+    #[arg(long, help = BENCH_HELP)]
+    pub bench: bool,
+
+    #[arg(long, value_name = "ENDPOINT_ID", requires = "bench", help = BENCH_ALLOW_HELP, action = ArgAction::Append)]
+    pub bench_allow: Vec<iroh::EndpointId>,
 
     #[arg(long, value_name = "DIR", help = KEY_DIR_HELP)]
     pub key_dir: Option<PathBuf>,
@@ -177,43 +528,236 @@ pub struct ServerArgs {
 
     #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
     pub extra_relay_url: Vec<String>,
+
+    #[arg(long, help = NO_RELAY_HELP, conflicts_with_all = ["relay_url", "extra_relay_url"])]
+    pub no_relay: bool,
+
+    #[arg(long, value_name = "FILE", help = RELAY_MAP_HELP, conflicts_with_all = ["relay_url", "extra_relay_url", "no_relay"])]
+    pub relay_map: Option<PathBuf>,
+
+    #[arg(long, value_name = "BACKEND", help = DISCOVERY_HELP, action = ArgAction::Append)]
+    pub discovery: Vec<DiscoveryBackend>,
+
+    #[arg(long, value_name = "POLICY", default_value_t = PathPolicy::Any, help = PATH_HELP)]
+    pub path: PathPolicy,
+
+    #[command(flatten)]
+    pub bind: BindOpts,
+
+    #[command(flatten)]
+    pub quic: QuicOpts,
+
+    #[arg(long, value_name = "CIDR", num_args = 0..=1, default_missing_value = "127.0.0.0/8", help = PEER_SOURCE_RANGE_HELP)]
+    pub peer_source_range: Option<SourceRange>,
+
+    #[arg(long, value_name = "NAME=ADDRESS", help = TARGET_SPEC_HELP, action = ArgAction::Append)]
+    pub target: Vec<TargetSpec>,
+
+    #[arg(long, value_name = "NAME=ENDPOINT_ID", help = ALLOW_RULE_HELP, action = ArgAction::Append)]
+    pub allow: Vec<AllowRule>,
+
+    #[arg(long, help = ADVERTISE_HELP)]
+    pub advertise: bool,
+
+    #[arg(long, value_name = "NAME", requires = "advertise", help = ADVERTISE_NAME_HELP)]
+    pub name: Option<String>,
 }
 
-#[derive(Args, Clone, Debug)]
-pub struct InfoArgs {
-    #[arg(long, value_name = "DIR", help = KEY_DIR_HELP)]
-    pub key_dir: Option<PathBuf>,
+impl ServerOpts {
+    /// The options as command line arguments, for services to start the server with.
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args = vec!["--ssh-port".to_string(), self.ssh_port.to_string()];
+        let mut push = |name: &str, value: String| {
+            args.push(format!("--{name}"));
+            args.push(value);
+        };
+        if let Some(host) = &self.ssh_host {
+            push("ssh-host", host.to_string());
+        }
+        for peer in &self.bench_allow {
+            push("bench-allow", peer.to_string());
+        }
+        if let Some(dir) = &self.key_dir {
+            push("key-dir", dir.display().to_string());
+        }
+        for url in &self.relay_url {
+            push("relay-url", url.clone());
+        }
+        for url in &self.extra_relay_url {
+            push("extra-relay-url", url.clone());
+        }
+        if let Some(path) = &self.relay_map {
+            push("relay-map", path.display().to_string());
+        }
+        for backend in &self.discovery {
+            push("discovery", backend.to_string());
+        }
+        if self.path != PathPolicy::Any {
+            push("path", self.path.to_string());
+        }
+        for addr in &self.bind.bind_addrs {
+            push("bind", addr.to_string());
+        }
+        if let Some(range) = self.peer_source_range {
+            push("peer-source-range", range.to_string());
+        }
+        for target in &self.target {
+            push("target", target.to_string());
+        }
+        for rule in &self.allow {
+            push("allow", rule.to_string());
+        }
+        if let Some(name) = &self.name {
+            push("name", name.clone());
+        }
+        if let Some(secs) = self.wait_for_sshd {
+            args.push(format!("--wait-for-sshd={secs}"));
+        }
+        for (flag, set) in [
+            ("--bench", self.bench),
+            ("--no-relay", self.no_relay),
+            ("--ipv4-only", self.bind.ipv4_only),
+            ("--ipv6-only", self.bind.ipv6_only),
+            ("--advertise", self.advertise),
+        ] {
+            if set {
+                args.push(flag.to_string());
+            }
+        }
+        args.extend(self.quic.args());
+        args
+    }
 }
 
 #[derive(Subcommand, Clone, Debug)]
-pub enum ServiceCmd {
-    Install {
-        #[arg(long, default_value = "22")]
-        ssh_port: u16,
+pub enum RelaysCmd {
+    /// Probe the relays for latency and show which one this machine picks as home relay
+    Check(RelaysCheckArgs),
+}
 
-        #[arg(long, value_name = "DIR", help = KEY_DIR_HELP)]
-        key_dir: Option<PathBuf>,
+#[derive(Args, Clone, Debug)]
+pub struct RelaysCheckArgs {
+    #[arg(long, value_name = "URL", help = RELAY_URL_HELP, action = ArgAction::Append)]
+    pub relay_url: Vec<String>,
 
-        #[arg(long, value_name = "URL", help = RELAY_URL_HELP, action = ArgAction::Append)]
-        relay_url: Vec<String>,
+    #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
+    pub extra_relay_url: Vec<String>,
 
-        #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
-        extra_relay_url: Vec<String>,
-    },
-    Uninstall,
+    #[arg(long, value_name = "FILE", help = RELAY_MAP_HELP, conflicts_with_all = ["relay_url", "extra_relay_url"])]
+    pub relay_map: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 10,
+        help = "Give up on relays that haven't answered after this long"
+    )]
+    pub timeout: u64,
 }
 
+/// Measure throughput, round trips and connect times to a server started with --bench
 #[derive(Args, Clone, Debug)]
-pub struct ServiceArgs {
-    #[arg(long, default_value = "22")]
-    pub ssh_port: u16,
+pub struct BenchArgs {
+    #[arg(help = "Endpoint ID, ticket or alias of the iroh-ssh server")]
+    pub endpoint_id: String,
 
-    #[arg(long, value_name = "DIR", help = KEY_DIR_HELP)]
-    pub key_dir: Option<PathBuf>,
+    #[arg(long = "addr", value_name = "IP:PORT", help = ADDR_HELP, action = ArgAction::Append)]
+    pub addrs: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "64M",
+        help = "Bytes to upload, and again to download"
+    )]
+    pub bytes: ByteSize,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 20,
+        help = "Round trips to time"
+    )]
+    pub pings: usize,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        help = "Connects to time after the first, cold one"
+    )]
+    pub connects: usize,
+
+    #[arg(
+        long,
+        help = "Print the results as JSON, e.g. for tracking regressions"
+    )]
+    pub json: bool,
 
     #[arg(long, value_name = "URL", help = RELAY_URL_HELP, action = ArgAction::Append)]
     pub relay_url: Vec<String>,
 
     #[arg(long, value_name = "URL", help = EXTRA_RELAY_URL_HELP, action = ArgAction::Append)]
     pub extra_relay_url: Vec<String>,
+
+    #[arg(long, help = NO_RELAY_HELP, conflicts_with_all = ["relay_url", "extra_relay_url"])]
+    pub no_relay: bool,
+
+    #[arg(long, value_name = "FILE", help = RELAY_MAP_HELP, conflicts_with_all = ["relay_url", "extra_relay_url", "no_relay"])]
+    pub relay_map: Option<PathBuf>,
+
+    #[arg(long, value_name = "BACKEND", help = DISCOVERY_HELP, action = ArgAction::Append)]
+    pub discovery: Vec<DiscoveryBackend>,
+
+    #[command(flatten)]
+    pub bind: BindOpts,
+
+    #[command(flatten)]
+    pub quic: QuicOpts,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct InfoArgs {
+    #[arg(long, value_name = "DIR", help = KEY_DIR_HELP)]
+    pub key_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct DiscoverArgs {
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 3,
+        help = "How long to listen for servers"
+    )]
+    pub timeout: u64,
+
+    #[arg(
+        long,
+        help = "Save the servers found as aliases without asking, an alias for another endpoint is only replaced after confirming"
+    )]
+    pub save: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct PeersArgs {
+    #[arg(help = "Also show the source address for these endpoint ids")]
+    pub endpoint_ids: Vec<String>,
+
+    #[arg(long, value_name = "DIR", help = KEY_DIR_HELP)]
+    pub key_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "CIDR",
+        default_value = "127.0.0.0/8",
+        help = "Source range the server was started with"
+    )]
+    pub peer_source_range: SourceRange,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum ServiceCmd {
+    Install(Box<ServerOpts>),
+    Uninstall,
 }
@@ -0,0 +1,116 @@
+use std::{fmt, io};
+
+use iroh::endpoint::{
+    Connection, ConnectionError, ReadError, RecvStream, SendStream, VarInt, WriteError,
+};
+
+/// Application error codes the server closes a tunnel, or its whole connection, with.
+///
+/// Clients turn these into [`crate::Error::TunnelClosed`] instead of a bare
+/// "connection closed".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CloseCode {
+    /// The client's target request was malformed.
+    InvalidRequest,
+    /// The server could not reach sshd or the requested target.
+    TargetUnavailable,
+    /// The client is not allowed to reach the requested target.
+    NotAuthorized,
+    /// The client has too many tunnels open to this server.
+    RateLimited,
+    /// The server is shutting down.
+    ShuttingDown,
+    /// The connection's path (direct or relayed) violates the server's path policy.
+    PathNotAllowed,
+}
+
+impl CloseCode {
+    pub fn code(self) -> u32 {
+        match self {
+            CloseCode::InvalidRequest => 1,
+            CloseCode::TargetUnavailable => 2,
+            CloseCode::NotAuthorized => 3,
+            CloseCode::RateLimited => 4,
+            CloseCode::ShuttingDown => 5,
+            CloseCode::PathNotAllowed => 6,
+        }
+    }
+
+    pub fn from_code(code: u64) -> Option<Self> {
+        match code {
+            1 => Some(CloseCode::InvalidRequest),
+            2 => Some(CloseCode::TargetUnavailable),
+            3 => Some(CloseCode::NotAuthorized),
+            4 => Some(CloseCode::RateLimited),
+            5 => Some(CloseCode::ShuttingDown),
+            6 => Some(CloseCode::PathNotAllowed),
+            _ => None,
+        }
+    }
+
+    /// The short reason sent along with the code.
+    pub fn reason(self) -> &'static str {
+        match self {
+            CloseCode::InvalidRequest => "invalid target request",
+            CloseCode::TargetUnavailable => "target unavailable",
+            CloseCode::NotAuthorized => "target not allowed",
+            CloseCode::RateLimited => "rate limited",
+            CloseCode::ShuttingDown => "server shutting down",
+            CloseCode::PathNotAllowed => "path not allowed",
+        }
+    }
+
+    pub(crate) fn close(self, connection: &Connection) {
+        connection.close(VarInt::from_u32(self.code()), self.reason().as_bytes());
+    }
+
+    /// The code the server closed `connection` with, if any.
+    pub(crate) fn from_connection(connection: &Connection) -> Option<Self> {
+        match connection.close_reason()? {
+            ConnectionError::ApplicationClosed(close) => {
+                Self::from_code(close.error_code.into_inner())
+            }
+            _ => None,
+        }
+    }
+
+    /// Ends a single tunnel, leaving other tunnels on its connection open.
+    pub(crate) fn reset(self, send: &mut SendStream, recv: &mut RecvStream) {
+        send.reset(VarInt::from_u32(self.code())).ok();
+        recv.stop(VarInt::from_u32(self.code())).ok();
+    }
+
+    /// The code a tunnel was reset with, from the error reading or writing it.
+    pub(crate) fn from_stream_error(err: &io::Error) -> Option<Self> {
+        let inner = err.get_ref()?;
+        let code = if let Some(ReadError::Reset(code)) = inner.downcast_ref() {
+            *code
+        } else if let Some(WriteError::Stopped(code)) = inner.downcast_ref() {
+            *code
+        } else {
+            return None;
+        };
+        Self::from_code(code.into_inner())
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseCode::InvalidRequest => write!(f, "the server did not understand the request"),
+            CloseCode::TargetUnavailable => {
+                write!(f, "the server could not reach its SSH server or the target")
+            }
+            CloseCode::NotAuthorized => write!(f, "this endpoint is not allowed to connect"),
+            CloseCode::RateLimited => write!(f, "too many connections, try again later"),
+            CloseCode::ShuttingDown => write!(f, "the server is shutting down"),
+            CloseCode::PathNotAllowed => {
+                write!(
+                    f,
+                    "the server's --path policy doesn't allow this connection's path"
+                )
+            }
+        }
+    }
+}
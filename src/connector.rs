@@ -0,0 +1,301 @@
+use std::{fmt, future::Future, io, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc};
+
+use iroh::EndpointId;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    net::{TcpSocket, TcpStream},
+    sync::mpsc,
+};
+
+use crate::{SourceRange, Targets, health::SshdHealth, peers::PeersFile};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A bidirectional byte stream a tunnel can be spliced onto.
+pub trait TargetStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> TargetStream for T {}
+
+/// What a client asked to be connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetRequest {
+    /// The server's ssh daemon ([`crate::IrohSsh::ALPN`]).
+    Ssh,
+    /// A named target ([`crate::IrohSsh::TARGET_ALPN`]).
+    Named(String),
+}
+
+impl fmt::Display for TargetRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetRequest::Ssh => write!(f, "local SSH server"),
+            TargetRequest::Named(name) => write!(f, "target '{name}'"),
+        }
+    }
+}
+
+/// Opens the server-side stream for every accepted tunnel.
+///
+/// Return an [`io::ErrorKind::PermissionDenied`] error to reject a peer.
+pub trait TargetConnector: Send + Sync + fmt::Debug + 'static {
+    fn connect<'a>(
+        &'a self,
+        remote: EndpointId,
+        request: &'a TargetRequest,
+    ) -> BoxFuture<'a, io::Result<Box<dyn TargetStream>>>;
+}
+
+/// Dials a fixed TCP address, optionally from a per-peer source address.
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    addr: SocketAddr,
+    source_range: Option<SourceRange>,
+    peers_file: Option<PeersFile>,
+}
+
+impl TcpConnector {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            source_range: None,
+            peers_file: None,
+        }
+    }
+
+    /// Binds each outgoing connection to the peer's address in `range`, see [`SourceRange`].
+    pub fn source_range(mut self, range: SourceRange) -> Self {
+        self.source_range = Some(range);
+        self
+    }
+
+    /// Records the peer mappings in `path`, see [`crate::load_peers`].
+    pub fn peers_file(mut self, path: Option<PathBuf>) -> Self {
+        self.peers_file = path.map(PeersFile::new);
+        self
+    }
+
+    pub async fn connect_tcp(&self, remote: EndpointId) -> io::Result<TcpStream> {
+        // per-peer source addresses are IPv4, an IPv6 sshd is dialed from the default one
+        let Some(range) = self.source_range.filter(|_| self.addr.is_ipv4()) else {
+            return TcpStream::connect(self.addr).await;
+        };
+
+        let source_addr = range.addr_for(&remote);
+        println!("Peer {remote} mapped to source address {source_addr}");
+        if let Some(peers_file) = &self.peers_file {
+            peers_file.record(remote, source_addr);
+        }
+
+        let socket = TcpSocket::new_v4()?;
+        match socket.bind(SocketAddr::from((source_addr, 0))) {
+            Ok(()) => socket.connect(self.addr).await,
+            Err(e) => {
+                println!(
+                    "Failed to bind source address {source_addr} ({e}), using the default source address"
+                );
+                TcpStream::connect(self.addr).await
+            }
+        }
+    }
+}
+
+impl TargetConnector for TcpConnector {
+    fn connect<'a>(
+        &'a self,
+        remote: EndpointId,
+        _request: &'a TargetRequest,
+    ) -> BoxFuture<'a, io::Result<Box<dyn TargetStream>>> {
+        Box::pin(async move {
+            let stream = self.connect_tcp(remote).await?;
+            Ok(Box::new(stream) as Box<dyn TargetStream>)
+        })
+    }
+}
+
+/// Dials a fixed Unix domain socket.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl TargetConnector for UnixConnector {
+    fn connect<'a>(
+        &'a self,
+        _remote: EndpointId,
+        _request: &'a TargetRequest,
+    ) -> BoxFuture<'a, io::Result<Box<dyn TargetStream>>> {
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(&self.path).await?;
+            Ok(Box::new(stream) as Box<dyn TargetStream>)
+        })
+    }
+}
+
+/// An in-process tunnel end handed to the application by [`MemoryConnector`].
+#[derive(Debug)]
+pub struct MemoryStream {
+    pub remote: EndpointId,
+    pub request: TargetRequest,
+    pub stream: DuplexStream,
+}
+
+/// Hands every tunnel to the application as an in-memory duplex stream.
+#[derive(Debug, Clone)]
+pub struct MemoryConnector {
+    tx: mpsc::Sender<MemoryStream>,
+    buffer_size: usize,
+}
+
+impl MemoryConnector {
+    /// Returns the connector and the receiver new tunnels are delivered on.
+    pub fn new(buffer_size: usize) -> (Self, mpsc::Receiver<MemoryStream>) {
+        let (tx, rx) = mpsc::channel(16);
+        (Self { tx, buffer_size }, rx)
+    }
+}
+
+impl TargetConnector for MemoryConnector {
+    fn connect<'a>(
+        &'a self,
+        remote: EndpointId,
+        request: &'a TargetRequest,
+    ) -> BoxFuture<'a, io::Result<Box<dyn TargetStream>>> {
+        Box::pin(async move {
+            let (local, app) = tokio::io::duplex(self.buffer_size);
+            self.tx
+                .send(MemoryStream {
+                    remote,
+                    request: request.clone(),
+                    stream: app,
+                })
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::ConnectionRefused, "memory listener closed")
+                })?;
+            Ok(Box::new(local) as Box<dyn TargetStream>)
+        })
+    }
+}
+
+/// The connector the CLI server uses: sshd over TCP plus the configured [`Targets`].
+#[derive(Debug, Clone)]
+pub(crate) struct DefaultConnector {
+    pub ssh: TcpConnector,
+    pub targets: Arc<Targets>,
+    /// Rejects sshd tunnels up front while it's down, see [`crate::Builder::wait_for_sshd`].
+    pub health: Option<SshdHealth>,
+}
+
+impl TargetConnector for DefaultConnector {
+    fn connect<'a>(
+        &'a self,
+        remote: EndpointId,
+        request: &'a TargetRequest,
+    ) -> BoxFuture<'a, io::Result<Box<dyn TargetStream>>> {
+        Box::pin(async move {
+            match request {
+                TargetRequest::Ssh => {
+                    if let Some(health) = &self.health
+                        && !health.is_up()
+                    {
+                        return Err(io::Error::new(
+                            io::ErrorKind::ConnectionRefused,
+                            "SSH server is unavailable",
+                        ));
+                    }
+                    let res = self.ssh.connect(remote, request).await;
+                    if res.is_err()
+                        && let Some(health) = &self.health
+                    {
+                        health.mark_down();
+                    }
+                    res
+                }
+                TargetRequest::Named(name) => match self.targets.resolve(name, &remote) {
+                    Some(addr) => {
+                        println!("Forwarding {remote} to target '{name}' ({addr})");
+                        addr.connect().await
+                    }
+                    None => Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("peer is not allowed to reach target '{name}'"),
+                    )),
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    #[tokio::test]
+    async fn memory_connector_hands_stream_to_application() {
+        let (connector, mut rx) = MemoryConnector::new(64);
+        let remote = SecretKey::generate(&mut rand::rng()).public();
+        let request = TargetRequest::Named("api".to_string());
+
+        let mut tunnel_end = connector.connect(remote, &request).await.unwrap();
+        let mut app_end = rx.recv().await.unwrap();
+        assert_eq!(app_end.remote, remote);
+        assert_eq!(app_end.request, request);
+
+        tunnel_end.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        app_end.stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn default_connector_rejects_unknown_targets() {
+        let connector = DefaultConnector {
+            ssh: TcpConnector::new(SocketAddr::from(([127, 0, 0, 1], 22))),
+            targets: Arc::new(Targets::default()),
+            health: None,
+        };
+        let remote = SecretKey::generate(&mut rand::rng()).public();
+        let err = connector
+            .connect(remote, &TargetRequest::Named("nas".to_string()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_connector_reaches_listening_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let remote = SecretKey::generate(&mut rand::rng()).public();
+
+        let connector = UnixConnector::new(path);
+        let mut tunnel_end = connector
+            .connect(remote, &TargetRequest::Named("console".to_string()))
+            .await
+            .unwrap();
+        let (mut target_end, _) = listener.accept().await.unwrap();
+
+        tunnel_end.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        target_end.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        target_end.write_all(b"pong").await.unwrap();
+        tunnel_end.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+}
@@ -0,0 +1,208 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWriteExt as _};
+
+use crate::Tunnel;
+
+/// Ctrl-], the same escape character telnet uses.
+pub const ESCAPE_CHAR: u8 = 0x1d;
+
+/// Attaches the local terminal to a tunnelled target until either side closes
+/// or the user presses the escape character.
+pub(crate) async fn attach(tunnel: Tunnel) -> io::Result<()> {
+    let _raw_mode = RawMode::enable();
+    let (mut recv, mut send) = tokio::io::split(tunnel);
+
+    let local_to_remote = async move {
+        let mut stdin = stdin();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stdin.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            if let Some(pos) = buf[..n].iter().position(|b| *b == ESCAPE_CHAR) {
+                send.write_all(&buf[..pos]).await?;
+                break;
+            }
+            send.write_all(&buf[..n]).await?;
+        }
+        send.shutdown().await.ok();
+        io::Result::Ok(())
+    };
+    let remote_to_local = async move {
+        let mut stdout = tokio::io::stdout();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = recv.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stdout.write_all(&buf[..n]).await?;
+            stdout.flush().await?;
+        }
+        io::Result::Ok(())
+    };
+
+    tokio::select! {
+        res = local_to_remote => res,
+        res = remote_to_local => res,
+    }
+}
+
+/// Stdin through the reactor where possible. `tokio::io::stdin()` reads on a blocking
+/// thread that keeps the runtime alive until one more key is pressed after detaching.
+fn stdin() -> Box<dyn AsyncRead + Send + Unpin> {
+    #[cfg(unix)]
+    if let Ok(stdin) = NonBlockingFd::new(libc::STDIN_FILENO) {
+        return Box::new(stdin);
+    }
+    Box::new(tokio::io::stdin())
+}
+
+/// A borrowed fd switched to non-blocking mode and read through tokio's reactor.
+/// The original file status flags are restored on drop, since they are shared
+/// with every other process holding the same terminal.
+#[cfg(unix)]
+struct NonBlockingFd {
+    fd: tokio::io::unix::AsyncFd<std::os::fd::RawFd>,
+    flags: libc::c_int,
+}
+
+#[cfg(unix)]
+impl NonBlockingFd {
+    /// Fails for fds epoll can't watch, such as regular files.
+    fn new(fd: std::os::fd::RawFd) -> io::Result<Self> {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match tokio::io::unix::AsyncFd::new(fd) {
+            Ok(fd) => Ok(Self { fd, flags }),
+            Err(err) => {
+                unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsyncRead for NonBlockingFd {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        loop {
+            let mut guard = std::task::ready!(self.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            let read = guard.try_io(|fd| {
+                let n = unsafe {
+                    libc::read(*fd.get_ref(), unfilled.as_mut_ptr().cast(), unfilled.len())
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match read {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return std::task::Poll::Ready(Ok(()));
+                }
+                Ok(Err(err)) => return std::task::Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for NonBlockingFd {
+    fn drop(&mut self) {
+        unsafe { libc::fcntl(*self.fd.get_ref(), libc::F_SETFL, self.flags) };
+    }
+}
+
+/// Puts stdin into raw mode while alive so keystrokes reach the remote device as typed.
+struct RawMode {
+    #[cfg(unix)]
+    original: Option<libc::termios>,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> Self {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return Self { original: None };
+            }
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Self { original: None };
+            }
+            let original = termios;
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Self { original: None };
+            }
+            Self {
+                original: Some(original),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl RawMode {
+    fn enable() -> Self {
+        Self {}
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn non_blocking_fd_reads_until_eof_and_restores_flags() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [read_fd, write_fd] = fds;
+        let before = unsafe { libc::fcntl(read_fd, libc::F_GETFL) };
+
+        let mut reader = NonBlockingFd::new(read_fd).unwrap();
+        assert_ne!(
+            unsafe { libc::fcntl(read_fd, libc::F_GETFL) } & libc::O_NONBLOCK,
+            0
+        );
+
+        let pending = tokio::spawn(async move {
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).await.map(|_| out)
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(
+            unsafe { libc::write(write_fd, b"hi".as_ptr().cast(), 2) },
+            2
+        );
+        unsafe { libc::close(write_fd) };
+
+        assert_eq!(pending.await.unwrap().unwrap(), b"hi");
+        assert_eq!(unsafe { libc::fcntl(read_fd, libc::F_GETFL) }, before);
+        unsafe { libc::close(read_fd) };
+    }
+}
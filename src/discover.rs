@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, fmt, str::FromStr, time::Duration};
+
+use iroh::{
+    Endpoint, EndpointAddr, EndpointId, SecretKey,
+    discovery::{
+        Discovery, DiscoveryError, DiscoveryItem, EndpointData, IntoDiscovery, IntoDiscoveryError,
+        UserData,
+        mdns::{DiscoveryEvent, MdnsDiscovery},
+    },
+};
+use n0_future::{StreamExt as _, boxed::BoxStream};
+use url::Url;
+
+/// A discovery mechanism an endpoint publishes its addresses to and resolves peers with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryBackend {
+    /// The public n0 DNS server (pkarr publishing, DNS lookups), iroh's default.
+    Dns,
+    /// A self-hosted pkarr relay, used for both publishing and lookups.
+    Pkarr(Url),
+    /// mDNS on the local network.
+    Local,
+    /// Nothing, peers are only reached at addresses they are given with.
+    None,
+}
+
+impl FromStr for DiscoveryBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dns" => Ok(Self::Dns),
+            "local" => Ok(Self::Local),
+            "none" => Ok(Self::None),
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(Self::Pkarr(Url::parse(url)?))
+            }
+            other => anyhow::bail!(
+                "unknown discovery '{other}', expected dns, local, none or a pkarr relay URL"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for DiscoveryBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dns => f.write_str("dns"),
+            Self::Pkarr(url) => write!(f, "{url}"),
+            Self::Local => f.write_str("local"),
+            Self::None => f.write_str("none"),
+        }
+    }
+}
+
+/// Marks the mDNS user data of iroh-ssh servers, so other iroh apps on the network are skipped.
+const ADVERT_PREFIX: &str = "iroh-ssh/";
+
+/// What a server started with `--advertise` announces on the local network.
+///
+/// It rides along as mDNS user data only, the endpoint's internet discovery
+/// record never carries it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalAdvert {
+    /// Friendly name, e.g. the hostname. Whitespace is replaced with `-`.
+    pub name: String,
+    /// User to log in as.
+    pub user: String,
+    /// iroh-ssh version of the server.
+    pub version: String,
+}
+
+impl LocalAdvert {
+    pub fn new(name: &str, user: &str) -> Self {
+        let clean = |s: &str| s.split_whitespace().collect::<Vec<_>>().join("-");
+        Self {
+            name: clean(name),
+            user: clean(user),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    fn to_user_data(&self) -> UserData {
+        let mut text = format!(
+            "{ADVERT_PREFIX}{} {} {}",
+            self.version, self.user, self.name
+        );
+        while text.len() > UserData::MAX_LENGTH {
+            text.pop();
+        }
+        UserData::try_from(text).expect("truncated to the max length")
+    }
+
+    fn parse(user_data: &str) -> Option<Self> {
+        let mut parts = user_data.strip_prefix(ADVERT_PREFIX)?.split_whitespace();
+        let (version, user, name) = (parts.next()?, parts.next()?, parts.next()?);
+        Some(Self {
+            name: name.to_string(),
+            user: user.to_string(),
+            version: version.to_string(),
+        })
+    }
+}
+
+/// mDNS discovery that publishes a [`LocalAdvert`] with the endpoint's addresses.
+#[derive(Debug)]
+pub(crate) struct AdvertisingMdns {
+    mdns: MdnsDiscovery,
+    user_data: UserData,
+}
+
+impl AdvertisingMdns {
+    pub(crate) fn builder(advert: &LocalAdvert) -> AdvertisingMdnsBuilder {
+        AdvertisingMdnsBuilder {
+            user_data: advert.to_user_data(),
+        }
+    }
+}
+
+impl Discovery for AdvertisingMdns {
+    fn publish(&self, data: &EndpointData) {
+        let mut data = data.clone();
+        data.set_user_data(Some(self.user_data.clone()));
+        self.mdns.publish(&data);
+    }
+
+    fn resolve(
+        &self,
+        endpoint_id: EndpointId,
+    ) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
+        self.mdns.resolve(endpoint_id)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AdvertisingMdnsBuilder {
+    user_data: UserData,
+}
+
+impl IntoDiscovery for AdvertisingMdnsBuilder {
+    fn into_discovery(self, endpoint: &Endpoint) -> Result<impl Discovery, IntoDiscoveryError> {
+        Ok(AdvertisingMdns {
+            mdns: MdnsDiscovery::builder().build(endpoint.id())?,
+            user_data: self.user_data,
+        })
+    }
+}
+
+/// An iroh-ssh server seen on the local network.
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    pub advert: LocalAdvert,
+    pub addr: EndpointAddr,
+}
+
+/// Listens for `duration` and returns the servers that advertised themselves, by name.
+pub async fn discover_local(duration: Duration) -> anyhow::Result<Vec<DiscoveredServer>> {
+    let mdns = MdnsDiscovery::builder()
+        .advertise(false)
+        .build(SecretKey::generate(&mut rand::rng()).public())?;
+    let mut events = mdns.subscribe().await;
+
+    let mut found = BTreeMap::new();
+    let listen = async {
+        while let Some(event) = events.next().await {
+            match event {
+                DiscoveryEvent::Discovered { endpoint_info, .. } => {
+                    let Some(advert) = endpoint_info
+                        .data
+                        .user_data()
+                        .and_then(|data| LocalAdvert::parse(data.as_ref()))
+                    else {
+                        continue;
+                    };
+                    let addr = endpoint_info.to_endpoint_addr();
+                    found.insert(addr.id, DiscoveredServer { advert, addr });
+                }
+                DiscoveryEvent::Expired { endpoint_id } => {
+                    found.remove(&endpoint_id);
+                }
+            }
+        }
+    };
+    tokio::time::timeout(duration, listen).await.ok();
+
+    let mut servers: Vec<_> = found.into_values().collect();
+    servers.sort_by(|a, b| a.advert.name.cmp(&b.advert.name));
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_backends_parse() {
+        for text in ["dns", "local", "none", "https://pkarr.example.com/pkarr"] {
+            let backend = DiscoveryBackend::from_str(text).unwrap();
+            assert_eq!(backend.to_string(), text);
+        }
+        assert_eq!(
+            DiscoveryBackend::from_str("https://pkarr.example.com").unwrap(),
+            DiscoveryBackend::Pkarr("https://pkarr.example.com/".parse().unwrap())
+        );
+        assert!(DiscoveryBackend::from_str("mdns").is_err());
+        assert!(DiscoveryBackend::from_str("https://").is_err());
+    }
+
+    #[test]
+    fn advert_roundtrips_through_user_data() {
+        let advert = LocalAdvert::new("build box", "alice");
+        assert_eq!(advert.name, "build-box");
+        let user_data = advert.to_user_data();
+        assert_eq!(LocalAdvert::parse(user_data.as_ref()), Some(advert));
+        assert_eq!(LocalAdvert::parse("some-other-app"), None);
+
+        let long = LocalAdvert::new(&"n".repeat(300), "alice");
+        assert_eq!(long.to_user_data().as_ref().len(), UserData::MAX_LENGTH);
+    }
+}
@@ -0,0 +1,113 @@
+use std::{io, net::SocketAddr, path::PathBuf};
+
+use iroh::{
+    EndpointId,
+    endpoint::{BindError, ConnectError, ConnectionError, ConnectionType},
+};
+
+use crate::{CloseCode, PathPolicy};
+
+/// Errors returned by the iroh-ssh library API.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A key file or the key directory could not be read or written.
+    #[error("failed to access {}", path.display())]
+    KeyIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// No persisted keys exist in the key directory.
+    #[error("no iroh-ssh keys found in {}, use --persist flag to create them", dir.display())]
+    KeyNotFound { dir: PathBuf },
+    /// A key file exists but does not hold a valid key.
+    #[error("invalid key in {}: {reason}", path.display())]
+    KeyDecode { path: PathBuf, reason: String },
+    /// Nothing that speaks ssh is listening on the sshd address.
+    #[error("no ssh server available on {addr}")]
+    SshdUnavailable { addr: SocketAddr },
+    /// The iroh endpoint could not be bound.
+    #[error("failed to bind iroh endpoint")]
+    EndpointBind(#[source] Box<BindError>),
+    /// A `--bind` address is in use or not on this host.
+    #[error("failed to bind {addr}, it is in use or not an address of this host")]
+    BindAddrUnavailable { addr: SocketAddr },
+    /// The peer could not be reached in time.
+    #[error("timed out connecting to {endpoint_id}")]
+    ConnectTimeout { endpoint_id: EndpointId },
+    /// The peer closed the connection instead of serving the tunnel.
+    #[error("{endpoint_id} rejected the connection: {reason}")]
+    PeerRejected {
+        endpoint_id: EndpointId,
+        reason: String,
+    },
+    /// The server closed the tunnel with one of its [`CloseCode`]s.
+    #[error("{endpoint_id} closed the tunnel: {code}")]
+    TunnelClosed {
+        endpoint_id: EndpointId,
+        code: CloseCode,
+    },
+    /// The connection moved to a path the client's [`PathPolicy`] doesn't allow.
+    #[error("connection to {endpoint_id} switched to {path}, which --path {policy} doesn't allow")]
+    PathNotAllowed {
+        endpoint_id: EndpointId,
+        path: ConnectionType,
+        policy: PathPolicy,
+    },
+    /// Connecting to the peer failed for another reason.
+    #[error("failed to connect to {endpoint_id}")]
+    Connect {
+        endpoint_id: EndpointId,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A relay URL given on the command line or to the builder did not parse.
+    #[error("invalid relay URL '{url}': {reason}")]
+    InvalidRelayUrl { url: String, reason: String },
+    /// The local ssh client could not be started.
+    #[error("failed to start the ssh client")]
+    SshSpawn(#[source] io::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl Error {
+    pub(crate) fn key_io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::KeyIo { path, source }
+    }
+
+    pub(crate) fn from_connect(endpoint_id: EndpointId, err: ConnectError) -> Self {
+        match err {
+            ConnectError::Connection { source, .. } => Self::from_connection(endpoint_id, *source),
+            err => Error::Connect {
+                endpoint_id,
+                source: Box::new(err),
+            },
+        }
+    }
+
+    pub(crate) fn from_connection(endpoint_id: EndpointId, err: ConnectionError) -> Self {
+        match err {
+            ConnectionError::TimedOut => Error::ConnectTimeout { endpoint_id },
+            ConnectionError::ApplicationClosed(close)
+                if let Some(code) = CloseCode::from_code(close.error_code.into_inner()) =>
+            {
+                Error::TunnelClosed { endpoint_id, code }
+            }
+            ConnectionError::ApplicationClosed(close) => Error::PeerRejected {
+                endpoint_id,
+                reason: String::from_utf8_lossy(&close.reason).into_owned(),
+            },
+            ConnectionError::ConnectionClosed(close) => Error::PeerRejected {
+                endpoint_id,
+                reason: String::from_utf8_lossy(&close.reason).into_owned(),
+            },
+            err => Error::Connect {
+                endpoint_id,
+                source: Box::new(err),
+            },
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fmt, time::Duration};
+
+use iroh::{EndpointId, endpoint::ConnectionType};
+
+use crate::TargetRequest;
+
+/// How many events a slow subscriber may fall behind before it starts missing some.
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Something that happened on the server side of a tunnel, see [`crate::IrohSsh::subscribe`].
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// A peer connected on one of the iroh-ssh ALPNs.
+    PeerConnected { remote: EndpointId },
+    /// The peer opened a tunnel stream and named what it wants to reach.
+    StreamOpened {
+        remote: EndpointId,
+        request: TargetRequest,
+    },
+    /// The target connector failed to reach the requested target.
+    TargetDialFailed {
+        remote: EndpointId,
+        request: TargetRequest,
+        error: String,
+    },
+    /// The connection to the peer switched between direct, relayed or mixed paths.
+    PathChanged {
+        remote: EndpointId,
+        path: ConnectionType,
+    },
+    /// A tunnel stream ended, successfully or not.
+    ///
+    /// Connections rejected before any stream was opened end with one of these too.
+    SessionClosed {
+        remote: EndpointId,
+        bytes: SessionBytes,
+        duration: Duration,
+        reason: CloseReason,
+    },
+}
+
+/// Bytes moved through a session, as seen from the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionBytes {
+    /// From the target to the peer.
+    pub sent: u64,
+    /// From the peer to the target.
+    pub received: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Both directions reached EOF.
+    Finished,
+    /// The peer sent a malformed target request.
+    InvalidRequest,
+    /// The peer is not allowed to reach the requested target.
+    NotAllowed,
+    /// The target could not be reached.
+    TargetUnavailable,
+    /// The peer already had as many sessions open as allowed.
+    RateLimited,
+    /// The server shut down while the session was open or being set up.
+    ShuttingDown,
+    /// The connection switched to a path the server's [`crate::PathPolicy`] doesn't allow.
+    PathNotAllowed,
+    /// The connection or a stream failed mid-session.
+    ConnectionLost(String),
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::Finished => write!(f, "finished"),
+            CloseReason::InvalidRequest => write!(f, "invalid request"),
+            CloseReason::NotAllowed => write!(f, "not allowed"),
+            CloseReason::TargetUnavailable => write!(f, "target unavailable"),
+            CloseReason::RateLimited => write!(f, "rate limited"),
+            CloseReason::ShuttingDown => write!(f, "server shutting down"),
+            CloseReason::PathNotAllowed => write!(f, "path not allowed"),
+            CloseReason::ConnectionLost(e) => write!(f, "connection lost: {e}"),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio::sync::watch;
+
+use crate::{Error, ssh::is_ssh_server_available};
+
+/// How often sshd is probed while it's down, so sessions resume quickly.
+const DOWN_INTERVAL: Duration = Duration::from_secs(2);
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the local sshd answers, see `--wait-for-sshd`.
+///
+/// sshd is only probed while it's down, every probe shows up in its log. A healthy
+/// sshd is dialed by sessions anyway, a failed dial marks it down ([`SshdHealth::mark_down`]).
+#[derive(Debug, Clone)]
+pub(crate) struct SshdHealth {
+    addr: SocketAddr,
+    up: Arc<watch::Sender<bool>>,
+}
+
+impl SshdHealth {
+    /// Starts probing sshd at `addr`, until the last clone is dropped.
+    pub(crate) fn spawn(addr: SocketAddr) -> Self {
+        let up = Arc::new(watch::channel(false).0);
+        tokio::spawn(Self::probe(addr, Arc::downgrade(&up), up.subscribe()));
+        Self { addr, up }
+    }
+
+    async fn probe(
+        addr: SocketAddr,
+        up: Weak<watch::Sender<bool>>,
+        mut changed: watch::Receiver<bool>,
+    ) {
+        loop {
+            let available = is_ssh_server_available(addr, PROBE_TIMEOUT).await.is_ok();
+            let Some(up) = up.upgrade() else {
+                break;
+            };
+            Self::set(&up, addr, available);
+            drop(up);
+
+            if available {
+                // probe again once a session fails to dial it
+                if changed.wait_for(|up| !*up).await.is_err() {
+                    break;
+                }
+            } else {
+                tokio::time::sleep(DOWN_INTERVAL).await;
+            }
+        }
+    }
+
+    fn set(up: &watch::Sender<bool>, addr: SocketAddr, available: bool) {
+        if up.send_replace(available) == available {
+            return;
+        }
+        if available {
+            println!("SSH server on {addr} is available");
+        } else {
+            println!("SSH server on {addr} is unavailable, rejecting sessions until it's back");
+        }
+    }
+
+    pub(crate) fn is_up(&self) -> bool {
+        *self.up.borrow()
+    }
+
+    /// Records a failed dial, so sessions are rejected until the next successful probe.
+    pub(crate) fn mark_down(&self) {
+        Self::set(&self.up, self.addr, false);
+    }
+
+    /// Resolves once sshd answers, or fails after `timeout`.
+    pub(crate) async fn wait(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let mut up = self.up.subscribe();
+        let available = async {
+            up.wait_for(|up| *up).await.ok();
+        };
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, available)
+                .await
+                .map_err(|_| Error::SshdUnavailable { addr: self.addr }),
+            None => {
+                available.await;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncWriteExt as _, net::TcpListener};
+
+    #[tokio::test]
+    async fn health_follows_sshd() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let health = SshdHealth::spawn(listener.local_addr().unwrap());
+        assert!(health.wait(Some(Duration::from_millis(200))).await.is_err());
+
+        let probes = Arc::new(AtomicUsize::new(0));
+        let accepted = probes.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(b"SSH-2.0-Test\r\n").await.ok();
+            }
+        });
+        health.wait(Some(Duration::from_secs(10))).await.unwrap();
+        assert!(health.is_up());
+
+        // an sshd that's up is left alone until a session fails to dial it
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(probes.load(Ordering::SeqCst), 1);
+        health.mark_down();
+        assert!(!health.is_up());
+        health.wait(Some(Duration::from_secs(10))).await.unwrap();
+        assert_eq!(probes.load(Ordering::SeqCst), 2);
+    }
+}
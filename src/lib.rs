@@ -1,19 +1,83 @@
+mod addr_cache;
+#[cfg(unix)]
+mod agent;
+mod aliases;
+mod bench;
+mod bind;
 mod cli;
+mod close;
+mod connector;
+mod console;
+mod discover;
+mod error;
+mod events;
+mod health;
+#[cfg(unix)]
+mod mux;
+mod path;
+mod peers;
+mod pump;
+mod quote;
+#[cfg(feature = "relay")]
+mod relay;
+mod relay_map;
+mod remote;
+mod retry;
+#[cfg(unix)]
+mod runtime_dir;
+#[cfg(unix)]
+mod serial;
 mod service;
 mod ssh;
+mod ssh_host;
+mod target;
+mod ticket;
+mod transport;
+mod tunnel;
 
-use std::path::PathBuf;
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
 use ed25519_dalek::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
-use iroh::{Endpoint, RelayUrl, protocol::Router};
+use iroh::{Endpoint, EndpointId, RelayMap, RelayUrl, protocol::Router};
+use tokio::sync::broadcast;
 
 pub mod api;
 
+pub use addr_cache::AddrCache;
+#[cfg(unix)]
+pub use agent::Agent;
+pub use aliases::{ALIASES_FILE_NAME, load_aliases};
+pub use bench::{BenchOptions, BenchReport, RttStats, Throughput};
+pub use bind::{BindAddr, IpFamily};
 pub use cli::*;
+pub use close::CloseCode;
+#[cfg(unix)]
+pub use connector::UnixConnector;
+pub use connector::{
+    BoxFuture, MemoryConnector, MemoryStream, TargetConnector, TargetRequest, TargetStream,
+    TcpConnector,
+};
+pub use discover::{DiscoveredServer, DiscoveryBackend, LocalAdvert, discover_local};
+pub use error::Error;
+pub use events::{CloseReason, EVENT_CHANNEL_CAPACITY, SessionBytes, SessionEvent};
+pub use path::PathPolicy;
+pub use peers::{SourceRange, load_peers};
+pub use relay_map::{RelayCheck, RelayStatus, load_relay_map};
+pub use retry::RetryPolicy;
 pub use service::Service;
-pub use service::ServiceParams;
 pub use service::{install_service, run_service, uninstall_service};
 pub use ssh::dot_ssh;
+pub use ssh_host::SshHost;
+pub use target::{AllowRule, TargetAddr, TargetSpec, Targets};
+pub use ticket::TICKET_FILE_NAME;
+pub use transport::{ByteSize, CongestionControl};
+pub use tunnel::Tunnel;
+
+pub use iroh_tickets::endpoint::EndpointTicket;
 
 #[derive(Debug, Clone)]
 pub struct IrohSsh {
@@ -21,15 +85,17 @@ pub struct IrohSsh {
     pub(crate) secret_key: [u8; SECRET_KEY_LENGTH],
     #[allow(dead_code)]
     pub(crate) public_key: [u8; PUBLIC_KEY_LENGTH],
-    pub(crate) inner: Option<Inner>,
-    pub(crate) ssh_port: u16,
-}
-
-#[derive(Debug, Clone)]
-pub(crate) struct Inner {
-    pub endpoint: Endpoint,
-    #[allow(dead_code)]
-    pub router: Router,
+    pub(crate) endpoint: Endpoint,
+    pub(crate) router: Option<Router>,
+    pub(crate) connector: Arc<dyn TargetConnector>,
+    pub(crate) events: broadcast::Sender<SessionEvent>,
+    pub(crate) sessions: Arc<Mutex<ssh::Sessions>>,
+    pub(crate) max_sessions_per_peer: Option<usize>,
+    pub(crate) addr_cache: Option<AddrCache>,
+    pub(crate) path_policy: PathPolicy,
+    pub(crate) sshd_health: Option<health::SshdHealth>,
+    pub(crate) bench: bool,
+    pub(crate) bench_allow: Arc<Vec<EndpointId>>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,7 +103,23 @@ pub struct Builder {
     secret_key: [u8; SECRET_KEY_LENGTH],
     accept_incoming: bool,
     accept_port: Option<u16>,
+    accept_host: Option<IpAddr>,
+    wait_for_sshd: bool,
+    bench: bool,
+    bench_allow: Vec<EndpointId>,
     key_dir: Option<PathBuf>,
     relay_urls: Vec<RelayUrl>,
     extra_relay_urls: Vec<RelayUrl>,
+    relay_map: Option<RelayMap>,
+    local_only: bool,
+    local_advert: Option<LocalAdvert>,
+    discovery: Vec<DiscoveryBackend>,
+    path_policy: PathPolicy,
+    bind: bind::BindConfig,
+    quic: transport::QuicTuning,
+    source_range: Option<SourceRange>,
+    targets: Targets,
+    target_connector: Option<Arc<dyn TargetConnector>>,
+    max_sessions_per_peer: Option<usize>,
+    addr_cache: Option<AddrCache>,
 }
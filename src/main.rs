@@ -5,14 +5,20 @@ use iroh_ssh::{Cli, Cmd, ConnectArgs, ServiceCmd, api};
 use anyhow::bail;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+async fn main() {
+    if let Err(err) = run(Cli::parse()).await {
+        eprintln!("Error: {err:?}");
+        std::process::exit(api::exit_code(&err));
+    }
+}
 
+async fn run(cli: Cli) -> anyhow::Result<()> {
     match cli.cmd {
         Some(Cmd::Connect(args)) => api::client_mode(args).await,
         Some(Cmd::Exec(args)) => {
             let conn_args = ConnectArgs {
                 ssh: args.ssh,
+                proxy: args.proxy,
                 remote_cmd: args.remote_cmd,
                 target: args.target,
                 relay_url: args.relay_url,
@@ -24,35 +30,31 @@ async fn main() -> anyhow::Result<()> {
         Some(Cmd::Service { op }) => {
             if !self_runas::is_elevated() {
                 self_runas::admin()?;
-                return Ok(());
+                Ok(())
             } else {
                 match op {
-                    ServiceCmd::Install {
-                        ssh_port,
-                        key_dir,
-                        relay_url,
-                        extra_relay_url,
-                    } => api::service::install(ssh_port, key_dir, relay_url, extra_relay_url).await,
+                    ServiceCmd::Install(opts) => api::service::install(*opts).await,
                     ServiceCmd::Uninstall => api::service::uninstall().await,
                 }
             }
         }
         Some(Cmd::Info(args)) => api::info_mode(args.key_dir).await,
+        Some(Cmd::Peers(args)) => api::peers_mode(args).await,
+        Some(Cmd::Discover(args)) => api::discover_mode(args).await,
+        Some(Cmd::Console(args)) => api::console_mode(args).await,
+        Some(Cmd::Agent(args)) => api::agent_mode(args).await,
+        Some(Cmd::Mux { op }) => api::mux_mode(op).await,
+        Some(Cmd::Relays { op }) => api::relays_mode(op).await,
+        Some(Cmd::Bench(args)) => api::bench_mode(args).await,
+        #[cfg(feature = "relay")]
+        Some(Cmd::Relay(args)) => api::relay_mode(args).await,
         Some(Cmd::Version) => {
             println!("iroh-ssh version {}", env!("CARGO_PKG_VERSION"));
             Ok(())
         }
         Some(Cmd::Proxy(args)) => api::proxy_mode(args).await,
         #[cfg(target_os = "windows")]
-        Some(Cmd::RunService(args)) => {
-            iroh_ssh::run_service(
-                args.ssh_port,
-                args.key_dir,
-                args.relay_url,
-                args.extra_relay_url,
-            )
-            .await
-        }
+        Some(Cmd::RunService(opts)) => iroh_ssh::run_service(opts).await,
         #[cfg(not(target_os = "windows"))]
         Some(Cmd::RunService(_)) => {
             bail!("service runtime is only available on windows");
@@ -60,6 +62,7 @@ async fn main() -> anyhow::Result<()> {
         None => {
             let conn_args = ConnectArgs {
                 ssh: cli.ssh,
+                proxy: cli.proxy,
                 remote_cmd: cli.remote_cmd.unwrap_or_default(),
                 target: cli.target.unwrap_or_default(),
                 relay_url: cli.relay_url,
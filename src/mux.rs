@@ -0,0 +1,96 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::process::Command;
+
+use crate::{runtime_dir, target::validate_target_name};
+
+/// How long an idle master stays up when `--mux-persist` isn't given.
+pub(crate) const DEFAULT_CONTROL_PERSIST: &str = "10m";
+
+/// The ControlPath for masters to a server, inside a private runtime dir.
+///
+/// ssh expands `%C` to a hash of the local host, endpoint id, port and user,
+/// which keeps the path below the unix socket length limit. Named targets get
+/// their own prefix since they share the endpoint id with the server's sshd.
+pub(crate) fn control_path(target_name: Option<&str>) -> io::Result<PathBuf> {
+    if let Some(name) = target_name {
+        validate_target_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+    let dir = runtime_dir::runtime_subdir("mux")?;
+    Ok(control_path_in(&dir, target_name))
+}
+
+fn control_path_in(dir: &Path, target_name: Option<&str>) -> PathBuf {
+    match target_name {
+        Some(name) => dir.join(format!("{name}-%C")),
+        None => dir.join("%C"),
+    }
+}
+
+/// Adds the ssh options that share one master connection per server.
+pub(crate) fn add_control_opts(cmd: &mut Command, control_path: &Path, persist: Option<&str>) {
+    cmd.arg("-o").arg("ControlMaster=auto");
+    cmd.arg("-o")
+        .arg(format!("ControlPath={}", control_path.display()));
+    cmd.arg("-o").arg(format!(
+        "ControlPersist={}",
+        persist.unwrap_or(DEFAULT_CONTROL_PERSIST)
+    ));
+}
+
+/// `ssh -O exit` for the master serving `target`.
+pub(crate) fn stop_command(
+    target: &str,
+    control_path: &Path,
+    port: Option<u16>,
+    login_user: Option<&str>,
+) -> Command {
+    let mut cmd = Command::new("ssh");
+    cmd.arg("-O").arg("exit");
+    cmd.arg("-o")
+        .arg(format!("ControlPath={}", control_path.display()));
+    if let Some(p) = port {
+        cmd.arg("-p").arg(p.to_string());
+    }
+    if let Some(u) = login_user {
+        cmd.arg("-l").arg(u);
+    }
+    cmd.arg(target);
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_path_is_keyed_by_target_name() {
+        let dir = Path::new("/run/user/1000/iroh-ssh/mux");
+        assert_eq!(control_path_in(dir, None), dir.join("%C"));
+        assert_eq!(control_path_in(dir, Some("nas")), dir.join("nas-%C"));
+
+        let stop = stop_command("endpoint123", &dir.join("%C"), Some(2222), Some("alice"));
+        let args: Vec<_> = stop
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "-O",
+                "exit",
+                "-o",
+                "ControlPath=/run/user/1000/iroh-ssh/mux/%C",
+                "-p",
+                "2222",
+                "-l",
+                "alice",
+                "endpoint123"
+            ]
+        );
+    }
+}
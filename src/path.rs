@@ -0,0 +1,106 @@
+use std::{fmt, str::FromStr};
+
+use iroh::{Endpoint, EndpointId, Watcher as _, endpoint::ConnectionType};
+
+/// Which network paths a connection may use, see `--path`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathPolicy {
+    /// Direct or relayed, whatever iroh picks.
+    #[default]
+    Any,
+    /// Never send data through a relay. Relays are disabled on the endpoint.
+    DirectOnly,
+    /// Only send data through the relay. The endpoint's sockets are bound to
+    /// loopback so iroh can't punch a hole to another host, a direct path that
+    /// forms anyway (to a peer on the same host) ends the connection.
+    RelayOnly,
+}
+
+impl PathPolicy {
+    /// Whether `path` is acceptable, an unconfirmed path ([`ConnectionType::None`]) always is.
+    pub fn allows(self, path: &ConnectionType) -> bool {
+        match (self, path) {
+            (PathPolicy::Any, _) | (_, ConnectionType::None) => true,
+            (PathPolicy::DirectOnly, path) => matches!(path, ConnectionType::Direct(_)),
+            (PathPolicy::RelayOnly, path) => matches!(path, ConnectionType::Relay(_)),
+        }
+    }
+
+    /// Resolves with the first path to `endpoint_id` this policy doesn't allow.
+    ///
+    /// Never resolves for [`PathPolicy::Any`] or when there's no connection to watch.
+    pub(crate) async fn violation(
+        self,
+        endpoint: &Endpoint,
+        endpoint_id: EndpointId,
+    ) -> ConnectionType {
+        if self != PathPolicy::Any
+            && let Some(mut watcher) = endpoint.conn_type(endpoint_id)
+        {
+            let mut path = watcher.get();
+            loop {
+                if !self.allows(&path) {
+                    return path;
+                }
+                match watcher.updated().await {
+                    Ok(next) => path = next,
+                    Err(_) => break,
+                }
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+impl FromStr for PathPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(PathPolicy::Any),
+            "direct-only" => Ok(PathPolicy::DirectOnly),
+            "relay-only" => Ok(PathPolicy::RelayOnly),
+            other => {
+                anyhow::bail!("unknown path '{other}', expected direct-only, relay-only or any")
+            }
+        }
+    }
+}
+
+impl fmt::Display for PathPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathPolicy::Any => f.write_str("any"),
+            PathPolicy::DirectOnly => f.write_str("direct-only"),
+            PathPolicy::RelayOnly => f.write_str("relay-only"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_parse_and_allow_paths() {
+        for text in ["any", "direct-only", "relay-only"] {
+            assert_eq!(PathPolicy::from_str(text).unwrap().to_string(), text);
+        }
+        assert!(PathPolicy::from_str("direct").is_err());
+
+        let ip = "192.168.1.5:40000".parse().unwrap();
+        let relay: iroh::RelayUrl = "https://relay.example.com".parse().unwrap();
+        let direct = ConnectionType::Direct(ip);
+        let relayed = ConnectionType::Relay(relay.clone());
+        let mixed = ConnectionType::Mixed(ip, relay);
+
+        assert!(PathPolicy::Any.allows(&mixed));
+        assert!(PathPolicy::DirectOnly.allows(&direct));
+        assert!(!PathPolicy::DirectOnly.allows(&relayed));
+        assert!(!PathPolicy::DirectOnly.allows(&mixed));
+        assert!(PathPolicy::RelayOnly.allows(&relayed));
+        assert!(!PathPolicy::RelayOnly.allows(&direct));
+        assert!(!PathPolicy::RelayOnly.allows(&mixed));
+        assert!(PathPolicy::RelayOnly.allows(&ConnectionType::None));
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, Write as _},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::bail;
+use iroh::EndpointId;
+
+pub const PEERS_FILE_NAME: &str = "irohssh_peers";
+
+/// Peers recorded at most, the least recently recorded ones are dropped first.
+const MAX_PEERS: usize = 256;
+
+/// Serializes [`record_peer`] across concurrent tunnels, the server is the file's only writer.
+static PEERS_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// IPv4 range the server picks per-peer source addresses from when dialing the
+/// local ssh server, so sshd, fail2ban and `last` can tell tunnelled peers apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceRange {
+    network: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl SourceRange {
+    pub fn new(network: Ipv4Addr, prefix_len: u8) -> anyhow::Result<Self> {
+        if prefix_len > 30 {
+            bail!("source range /{prefix_len} is too small, use at most /30");
+        }
+        let mask = u32::MAX << (32 - prefix_len);
+        Ok(Self {
+            network: Ipv4Addr::from(u32::from(network) & mask),
+            prefix_len,
+        })
+    }
+
+    /// Deterministically maps an endpoint id to a host address inside the range.
+    ///
+    /// The network and broadcast addresses are never returned.
+    pub fn addr_for(&self, endpoint_id: &EndpointId) -> Ipv4Addr {
+        let hash = endpoint_id
+            .as_bytes()
+            .chunks_exact(4)
+            .fold(0u32, |acc, chunk| {
+                acc.rotate_left(5) ^ u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])
+            });
+        let hosts = (1u64 << (32 - self.prefix_len)) - 2;
+        let host = (hash as u64 % hosts) as u32 + 1;
+        Ipv4Addr::from(u32::from(self.network) | host)
+    }
+}
+
+impl Default for SourceRange {
+    fn default() -> Self {
+        Self {
+            network: Ipv4Addr::new(127, 0, 0, 0),
+            prefix_len: 8,
+        }
+    }
+}
+
+impl fmt::Display for SourceRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl FromStr for SourceRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix_len) = s.split_once('/').ok_or_else(|| {
+            anyhow::anyhow!("invalid source range '{s}', expected e.g. 127.0.0.0/8")
+        })?;
+        let network = Ipv4Addr::from_str(network)
+            .map_err(|e| anyhow::anyhow!("invalid source range '{s}': {e}"))?;
+        let prefix_len = u8::from_str(prefix_len)
+            .map_err(|e| anyhow::anyhow!("invalid source range '{s}': {e}"))?;
+        Self::new(network, prefix_len)
+    }
+}
+
+pub(crate) fn peers_file(ssh_dir: &Path) -> PathBuf {
+    ssh_dir.join(PEERS_FILE_NAME)
+}
+
+/// Reads the `<endpoint_id> <source_addr>` lines the server records per peer.
+pub fn load_peers(path: &Path) -> anyhow::Result<Vec<(EndpointId, Ipv4Addr)>> {
+    Ok(parse_peers(&read_peers_file(path)?))
+}
+
+fn read_peers_file(path: &Path) -> io::Result<String> {
+    match std::fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        res => res,
+    }
+}
+
+fn parse_peers(content: &str) -> Vec<(EndpointId, Ipv4Addr)> {
+    let mut peers = Vec::new();
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(id), Some(addr)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if let (Ok(id), Ok(addr)) = (EndpointId::from_str(id), Ipv4Addr::from_str(addr)) {
+            peers.push((id, addr));
+        }
+    }
+    peers
+}
+
+/// The server's peers file, written at most once per peer while it runs.
+#[derive(Debug, Clone)]
+pub(crate) struct PeersFile {
+    path: PathBuf,
+    seen: Arc<Mutex<HashSet<EndpointId>>>,
+}
+
+impl PeersFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            seen: Default::default(),
+        }
+    }
+
+    /// Records `endpoint_id` in the background the first time it's seen,
+    /// its address only changes with the source range.
+    pub(crate) fn record(&self, endpoint_id: EndpointId, addr: Ipv4Addr) {
+        if !self.seen().insert(endpoint_id) {
+            return;
+        }
+        let peers_file = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = record_peer(&peers_file.path, &endpoint_id, addr) {
+                tracing::warn!(
+                    "failed to record peer in {}: {e:#}",
+                    peers_file.path.display()
+                );
+                peers_file.seen().remove(&endpoint_id);
+            }
+        });
+    }
+
+    fn seen(&self) -> MutexGuard<'_, HashSet<EndpointId>> {
+        self.seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Adds a peer mapping unless the same mapping is already recorded, replacing
+/// an older mapping of the peer and keeping the last [`MAX_PEERS`].
+///
+/// The file is replaced through a temp file, so readers never see a partial line.
+pub(crate) fn record_peer(
+    path: &Path,
+    endpoint_id: &EndpointId,
+    addr: Ipv4Addr,
+) -> anyhow::Result<()> {
+    let _lock = PEERS_FILE_LOCK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let mut peers = parse_peers(&read_peers_file(path)?);
+    if peers.contains(&(*endpoint_id, addr)) {
+        return Ok(());
+    }
+    peers.retain(|(id, _)| id != endpoint_id);
+    peers.push((*endpoint_id, addr));
+    let skip = peers.len().saturating_sub(MAX_PEERS);
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    for (id, addr) in &peers[skip..] {
+        writeln!(tmp, "{id} {addr}")?;
+    }
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    fn addr_for_stays_inside_range() {
+        let range = SourceRange::from_str("10.20.30.0/24").unwrap();
+        for _ in 0..64 {
+            let id = SecretKey::generate(&mut rand::rng()).public();
+            let addr = range.addr_for(&id);
+            let octets = addr.octets();
+            assert_eq!(&octets[..3], &[10, 20, 30]);
+            assert!(octets[3] != 0 && octets[3] != 255);
+            assert_eq!(addr, range.addr_for(&id));
+        }
+    }
+
+    #[test]
+    fn source_range_normalizes_network() {
+        let range = SourceRange::from_str("127.1.2.3/8").unwrap();
+        assert_eq!(range, SourceRange::default());
+        assert_eq!(range.to_string(), "127.0.0.0/8");
+        assert!(SourceRange::from_str("127.0.0.1/31").is_err());
+        assert!(SourceRange::from_str("127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn concurrent_records_keep_every_peer_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = peers_file(dir.path());
+        let range = SourceRange::default();
+        let ids: Vec<_> = (0..16)
+            .map(|_| SecretKey::generate(&mut rand::rng()).public())
+            .collect();
+
+        std::thread::scope(|scope| {
+            for id in &ids {
+                for _ in 0..2 {
+                    let path = &path;
+                    scope.spawn(move || record_peer(path, id, range.addr_for(id)).unwrap());
+                }
+            }
+        });
+
+        let peers = load_peers(&path).unwrap();
+        assert_eq!(peers.len(), ids.len());
+        for id in &ids {
+            assert!(peers.contains(&(*id, range.addr_for(id))));
+        }
+    }
+
+    #[test]
+    fn records_replace_old_mappings_and_stay_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = peers_file(dir.path());
+        let ids: Vec<_> = (0..MAX_PEERS + 1)
+            .map(|_| SecretKey::generate(&mut rand::rng()).public())
+            .collect();
+
+        record_peer(&path, &ids[0], Ipv4Addr::new(127, 0, 0, 2)).unwrap();
+        for id in &ids {
+            record_peer(&path, id, Ipv4Addr::new(127, 0, 0, 3)).unwrap();
+        }
+
+        let peers = load_peers(&path).unwrap();
+        assert_eq!(peers.len(), MAX_PEERS);
+        assert!(!peers.iter().any(|(id, _)| *id == ids[0]));
+        assert_eq!(
+            peers.last(),
+            Some(&(ids[MAX_PEERS], Ipv4Addr::new(127, 0, 0, 3)))
+        );
+
+        record_peer(&path, &ids[1], Ipv4Addr::new(127, 0, 0, 4)).unwrap();
+        let peers = load_peers(&path).unwrap();
+        assert_eq!(peers.len(), MAX_PEERS);
+        assert_eq!(peers.last(), Some(&(ids[1], Ipv4Addr::new(127, 0, 0, 4))));
+    }
+}
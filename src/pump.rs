@@ -0,0 +1,89 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+/// Bytes read per direction at once, enough to keep a QUIC stream with large
+/// windows busy where tokio's 8 KiB copy buffer leaves it waiting on syscalls.
+pub(crate) const PUMP_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Copies `reader` to `writer` until EOF and returns the bytes copied.
+///
+/// Every read is written and flushed before the next one, so a slow writer
+/// holds the reader back instead of data piling up in between.
+pub(crate) async fn pump<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; PUMP_BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(copied);
+        }
+        writer.write_all(&buf[..n]).await?;
+        writer.flush().await?;
+        copied += n as u64;
+    }
+}
+
+/// Pumps both directions between `a` and `b` until both reach EOF, shutting
+/// down each writer once its reader is done, like [`tokio::io::copy_bidirectional`].
+///
+/// Returns the bytes copied from `a` to `b` and from `b` to `a`.
+pub(crate) async fn pump_bidirectional<A, B>(a: &mut A, b: &mut B) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    let a_to_b = async {
+        let copied = pump(&mut a_read, &mut b_write).await?;
+        b_write.shutdown().await?;
+        Ok::<_, io::Error>(copied)
+    };
+    let b_to_a = async {
+        let copied = pump(&mut b_read, &mut a_write).await?;
+        a_write.shutdown().await?;
+        Ok(copied)
+    };
+    tokio::try_join!(a_to_b, b_to_a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pump_copies_both_ways_with_backpressure() {
+        // a pipe much smaller than the pump buffer, the pump has to wait for the reader
+        let (near, mut far) = tokio::io::duplex(1024);
+        let (peer, mut echo) = tokio::io::duplex(1024);
+        let data: Vec<u8> = (0..PUMP_BUFFER_SIZE * 3).map(|i| i as u8).collect();
+
+        let pumped = tokio::spawn(async move { pump_bidirectional(&mut far, &mut echo).await });
+        let echoed = tokio::spawn(async move {
+            let (mut read, mut write) = tokio::io::split(peer);
+            let copied = pump(&mut read, &mut write).await;
+            write.shutdown().await.unwrap();
+            copied
+        });
+
+        let sent = data.clone();
+        let (mut read, mut write) = tokio::io::split(near);
+        let writer = tokio::spawn(async move {
+            write.write_all(&sent).await.unwrap();
+            write.shutdown().await.unwrap();
+        });
+        let mut received = Vec::new();
+        read.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+
+        assert_eq!(received, data);
+        assert_eq!(echoed.await.unwrap().unwrap(), data.len() as u64);
+        let (out, back) = pumped.await.unwrap().unwrap();
+        assert_eq!((out, back), (data.len() as u64, data.len() as u64));
+    }
+}
@@ -0,0 +1,24 @@
+/// Quotes `arg` as a single word for `/bin/sh`, leaving plain words as they are.
+pub(crate) fn sh(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sh_quotes_only_what_needs_it() {
+        assert_eq!(sh("/usr/bin/iroh-ssh"), "/usr/bin/iroh-ssh");
+        assert_eq!(sh("/home/me/My Files/a;b"), "'/home/me/My Files/a;b'");
+        assert_eq!(sh("it's"), r"'it'\''s'");
+        assert_eq!(sh(""), "''");
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Context as _;
+use iroh::EndpointId;
+use iroh_relay::{
+    defaults::{DEFAULT_HTTP_PORT, DEFAULT_HTTPS_PORT, DEFAULT_RELAY_QUIC_PORT},
+    server::{
+        Access, AccessConfig, CertConfig, QuicConfig, RelayConfig, Server, ServerConfig, TlsConfig,
+    },
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject as _};
+
+use crate::cli::RelayArgs;
+
+/// Plain HTTP port in `--dev` mode, the same as `iroh-relay --dev`.
+const DEV_MODE_HTTP_PORT: u16 = 3340;
+
+/// Runs a relay server until Ctrl+C.
+pub(crate) async fn run(args: RelayArgs) -> anyhow::Result<()> {
+    let mut server = Server::spawn(server_config(&args)?)
+        .await
+        .context("failed to start the relay server")?;
+
+    let url = match (server.https_addr(), server.http_addr()) {
+        (Some(addr), _) if addr.port() == DEFAULT_HTTPS_PORT => "https://<HOST>".to_string(),
+        (Some(addr), _) => format!("https://<HOST>:{}", addr.port()),
+        (None, Some(addr)) => format!("http://<HOST>:{}", addr.port()),
+        (None, None) => anyhow::bail!("relay server is not listening"),
+    };
+    println!("iroh relay {} running", env!("CARGO_PKG_VERSION"));
+    if let Some(addr) = server.http_addr() {
+        println!("  http:  {addr}");
+    }
+    if let Some(addr) = server.https_addr() {
+        println!("  https: {addr}");
+    }
+    if let Some(addr) = server.quic_addr() {
+        println!("  quic:  {addr} (address discovery)");
+    }
+    match args.allow.len() {
+        0 => println!("  open to every endpoint"),
+        n => println!("  open to {n} allowed endpoint(s)"),
+    }
+    println!();
+    println!(
+        "Use it on servers and clients, with <HOST> the name in the certificate or this machine's ip:"
+    );
+    println!();
+    println!("  iroh-ssh server --relay-url {url}");
+    println!("  iroh-ssh user@<ENDPOINT_ID> --relay-url {url}");
+    println!();
+    println!("Press Ctrl+C to exit");
+
+    tokio::select! {
+        biased;
+        _ = tokio::signal::ctrl_c() => {}
+        res = server.task_handle() => {
+            res.context("relay server task panicked")?
+                .context("relay server failed")?;
+        }
+    }
+    server.shutdown().await.ok();
+    Ok(())
+}
+
+fn server_config(args: &RelayArgs) -> anyhow::Result<ServerConfig<io::Error>> {
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(load_tls(cert, key)?),
+        _ if args.dev => None,
+        _ => anyhow::bail!("a relay needs --tls-cert and --tls-key, or --dev for plain http"),
+    };
+
+    let http_port = if args.dev {
+        DEV_MODE_HTTP_PORT
+    } else {
+        DEFAULT_HTTP_PORT
+    };
+    let http_bind_addr = args
+        .http_bind
+        .unwrap_or((Ipv6Addr::UNSPECIFIED, http_port).into());
+    let https_bind_addr = args
+        .https_bind
+        .unwrap_or((Ipv6Addr::UNSPECIFIED, DEFAULT_HTTPS_PORT).into());
+    let quic_bind_addr: SocketAddr = (Ipv6Addr::UNSPECIFIED, DEFAULT_RELAY_QUIC_PORT).into();
+
+    let quic = tls.as_ref().map(|(_, server_config)| QuicConfig {
+        bind_addr: quic_bind_addr,
+        server_config: server_config.clone(),
+    });
+    let tls = tls.map(|(certs, server_config)| TlsConfig {
+        https_bind_addr,
+        quic_bind_addr,
+        cert: CertConfig::Manual { certs },
+        server_config,
+    });
+
+    Ok(ServerConfig {
+        relay: Some(RelayConfig {
+            http_bind_addr,
+            tls,
+            limits: Default::default(),
+            key_cache_capacity: None,
+            access: access_config(args.allow.clone()),
+        }),
+        quic,
+        metrics_addr: None,
+    })
+}
+
+fn load_tls(
+    cert: &Path,
+    key: &Path,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, rustls::ServerConfig)> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read certificates from {}", cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("failed to read private key from {}", key.display()))?;
+    let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("protocols supported by ring")
+    .with_no_client_auth()
+    .with_single_cert(certs.clone(), key)
+    .context("invalid tls certificate or key")?;
+    Ok((certs, server_config))
+}
+
+/// Everyone, or only the endpoints in `allow` when it's not empty.
+fn access_config(allow: Vec<EndpointId>) -> AccessConfig {
+    if allow.is_empty() {
+        return AccessConfig::Everyone;
+    }
+    AccessConfig::Restricted(Box::new(move |endpoint_id| {
+        let access = if allow.contains(&endpoint_id) {
+            Access::Allow
+        } else {
+            Access::Deny
+        };
+        Box::pin(async move { access })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[tokio::test]
+    async fn allowlist_restricts_access() {
+        let allowed = SecretKey::generate(&mut rand::rng()).public();
+        let other = SecretKey::generate(&mut rand::rng()).public();
+
+        let access = access_config(vec![allowed]);
+        assert!(access.is_allowed(allowed).await);
+        assert!(!access.is_allowed(other).await);
+        assert!(access_config(Vec::new()).is_allowed(other).await);
+    }
+}
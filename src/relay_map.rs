@@ -0,0 +1,197 @@
+use std::{path::Path, str::FromStr as _, sync::Arc, time::Duration};
+
+use anyhow::Context as _;
+use iroh::{RelayConfig, RelayMap, RelayMode, RelayUrl, Watcher as _, net_report::Probe};
+use serde::Deserialize;
+
+use crate::{Error, IrohSsh};
+
+/// A `--relay-map` file: the relays to use, each with its own options.
+///
+/// ```toml
+/// # keep the default relays next to these (default: false)
+/// defaults = false
+///
+/// [[relay]]
+/// url = "https://relay.example.com"
+/// # UDP port of the relay's QUIC address discovery (default: 7842)
+/// quic_port = 7843
+///
+/// [[relay]]
+/// url = "http://10.0.0.5:3340"
+/// # don't try QUIC address discovery with this relay
+/// quic = false
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RelayMapFile {
+    #[serde(default)]
+    defaults: bool,
+    #[serde(default)]
+    relay: Vec<RelayEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RelayEntry {
+    url: String,
+    quic_port: Option<u16>,
+    #[serde(default = "quic_default")]
+    quic: bool,
+}
+
+fn quic_default() -> bool {
+    true
+}
+
+/// Reads a relay map file, see [`RelayMapFile`] for the format.
+pub fn load_relay_map(path: &Path) -> anyhow::Result<RelayMap> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read relay map {}", path.display()))?;
+    parse_relay_map(&text).with_context(|| format!("invalid relay map {}", path.display()))
+}
+
+fn parse_relay_map(text: &str) -> anyhow::Result<RelayMap> {
+    let file: RelayMapFile = toml::from_str(text)?;
+    let relay_map = if file.defaults {
+        RelayMode::Default.relay_map()
+    } else {
+        RelayMap::empty()
+    };
+    for entry in file.relay {
+        let url = RelayUrl::from_str(&entry.url).map_err(|e| Error::InvalidRelayUrl {
+            url: entry.url.clone(),
+            reason: e.to_string(),
+        })?;
+        let mut config = RelayConfig::from(url.clone());
+        match (entry.quic, entry.quic_port) {
+            (false, Some(_)) => anyhow::bail!("{url} sets both quic = false and a quic_port"),
+            (false, None) => config.quic = None,
+            (true, Some(port)) => {
+                if let Some(quic) = &mut config.quic {
+                    quic.port = port;
+                }
+            }
+            (true, None) => {}
+        }
+        relay_map.insert(url, Arc::new(config));
+    }
+    if relay_map.is_empty() {
+        anyhow::bail!("no relays, add a [[relay]] entry or set defaults = true");
+    }
+    Ok(relay_map)
+}
+
+/// How a relay answered the endpoint's probes, see [`IrohSsh::check_relays`].
+#[derive(Debug, Clone)]
+pub struct RelayStatus {
+    pub url: RelayUrl,
+    /// Lowest round trip over any probe, `None` if the relay wasn't reached.
+    pub latency: Option<Duration>,
+    /// Whether QUIC address discovery with the relay worked.
+    pub quic: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayCheck {
+    pub relays: Vec<RelayStatus>,
+    /// The relay the endpoint connected to and publishes as its home relay.
+    pub home_relay: Option<RelayUrl>,
+}
+
+impl IrohSsh {
+    /// Waits up to `timeout` for the endpoint to probe every relay in `relays`
+    /// and to pick its home relay, then reports what it saw.
+    pub async fn check_relays(&self, relays: &RelayMap, timeout: Duration) -> RelayCheck {
+        let urls: Vec<RelayUrl> = relays.urls();
+        let mut reports = self.endpoint.net_report();
+        let probed_all = async {
+            self.endpoint.online().await;
+            loop {
+                if let Some(report) = reports.get()
+                    && urls.iter().all(|url| {
+                        report
+                            .relay_latency
+                            .iter()
+                            .any(|(_, probed, _)| probed == url)
+                    })
+                {
+                    break;
+                }
+                if reports.updated().await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(timeout, probed_all).await.ok();
+
+        let report = reports.get().unwrap_or_default();
+        let relays = urls
+            .into_iter()
+            .map(|url| {
+                let probes: Vec<_> = report
+                    .relay_latency
+                    .iter()
+                    .filter(|(_, probed, _)| **probed == url)
+                    .collect();
+                RelayStatus {
+                    latency: probes.iter().map(|(_, _, latency)| *latency).min(),
+                    quic: probes.iter().any(|(probe, _, _)| *probe != Probe::Https),
+                    url,
+                }
+            })
+            .collect();
+        RelayCheck {
+            relays,
+            home_relay: self.endpoint.addr().relay_urls().next().cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relay_map_files_parse() {
+        let relay_map = parse_relay_map(
+            r#"
+            [[relay]]
+            url = "https://relay.example.com"
+            quic_port = 7843
+
+            [[relay]]
+            url = "http://10.0.0.5:3340"
+            quic = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(relay_map.len(), 2);
+        let relay = relay_map
+            .get(&"https://relay.example.com".parse().unwrap())
+            .unwrap();
+        assert_eq!(relay.quic.as_ref().unwrap().port, 7843);
+        let relay = relay_map
+            .get(&"http://10.0.0.5:3340".parse().unwrap())
+            .unwrap();
+        assert!(relay.quic.is_none());
+
+        let with_defaults =
+            parse_relay_map("defaults = true\n[[relay]]\nurl = \"https://relay.example.com\"\n")
+                .unwrap();
+        assert_eq!(
+            with_defaults.len(),
+            RelayMode::Default.relay_map().len() + 1
+        );
+
+        assert!(parse_relay_map("").is_err());
+        assert!(parse_relay_map("[[relay]]\nurl = \"not a url\"\n").is_err());
+        assert!(parse_relay_map("[[relay]]\nurl = \"https://a.example\"\nport = 1\n").is_err());
+        assert!(
+            parse_relay_map(
+                "[[relay]]\nurl = \"https://a.example\"\nquic = false\nquic_port = 1\n"
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::{net::SocketAddr, str::FromStr as _};
+
+use anyhow::Context as _;
+use iroh::{EndpointAddr, EndpointId, TransportAddr};
+use iroh_tickets::endpoint::EndpointTicket;
+
+/// Separates the endpoint id from its direct addresses in a full endpoint address.
+const ADDR_SEPARATOR: char = '+';
+
+/// Parses the host part of a target: `<ENDPOINT_ID|TICKET>[+<ip:port>...]`.
+///
+/// Returns `None` for hosts that aren't an endpoint id or ticket, e.g. DNS
+/// names that `proxy` dials over plain TCP.
+pub(crate) fn parse_endpoint_addr(host: &str) -> anyhow::Result<Option<EndpointAddr>> {
+    let mut parts = host.split(ADDR_SEPARATOR);
+    let id = parts.next().unwrap_or_default();
+    let mut addr = if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        EndpointAddr::new(EndpointId::from_str(id)?)
+    } else if let Ok(ticket) = EndpointTicket::from_str(id) {
+        ticket.into()
+    } else {
+        return Ok(None);
+    };
+    for part in parts {
+        let ip_addr = SocketAddr::from_str(part)
+            .with_context(|| format!("invalid direct address '{part}' in '{host}'"))?;
+        addr = addr.with_ip_addr(ip_addr);
+    }
+    Ok(Some(addr))
+}
+
+/// Parses the `%h:%p` argument ssh hands to `proxy`, the port is ignored.
+pub(crate) fn parse_proxy_host(host_port: &str) -> anyhow::Result<Option<EndpointAddr>> {
+    let first = parse_endpoint_addr(host_port);
+    if let Ok(Some(addr)) = first {
+        return Ok(Some(addr));
+    }
+    match host_port.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => parse_endpoint_addr(host),
+        _ => first,
+    }
+}
+
+/// Formats `addr` the way [`parse_endpoint_addr`] reads it, keeping only direct addresses.
+pub(crate) fn format_endpoint_addr(addr: &EndpointAddr) -> String {
+    let mut out = addr.id.to_string();
+    for transport in &addr.addrs {
+        if let TransportAddr::Ip(ip_addr) = transport {
+            out.push(ADDR_SEPARATOR);
+            out.push_str(&ip_addr.to_string());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    fn endpoint_addrs_parse_with_and_without_port() {
+        let id = SecretKey::generate(&mut rand::rng()).public();
+        let addr = EndpointAddr::new(id)
+            .with_ip_addr("192.168.1.5:40000".parse().unwrap())
+            .with_ip_addr("[fe80::1]:40000".parse().unwrap());
+        let text = format_endpoint_addr(&addr);
+
+        assert_eq!(parse_endpoint_addr(&text).unwrap(), Some(addr.clone()));
+        assert_eq!(parse_proxy_host(&text).unwrap(), Some(addr.clone()));
+        assert_eq!(parse_proxy_host(&format!("{text}:22")).unwrap(), Some(addr));
+        assert_eq!(
+            parse_proxy_host(&format!("{id}:22")).unwrap(),
+            Some(EndpointAddr::new(id))
+        );
+        assert_eq!(parse_proxy_host("example.com:22").unwrap(), None);
+        assert!(parse_endpoint_addr(&format!("{id}+nonsense")).is_err());
+
+        let ticket = EndpointTicket::new(
+            EndpointAddr::new(id).with_relay_url("https://relay.example.com".parse().unwrap()),
+        );
+        assert_eq!(
+            parse_proxy_host(&format!("{ticket}+192.168.1.5:40000:22")).unwrap(),
+            Some(
+                ticket
+                    .endpoint_addr()
+                    .clone()
+                    .with_ip_addr("192.168.1.5:40000".parse().unwrap())
+            )
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use crate::Error;
+
+/// How [`crate::IrohSsh::open_with_retry`] retries a peer that can't be reached.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Give up on a single attempt after this long, `None` leaves it to iroh.
+    pub connect_timeout: Option<Duration>,
+    /// Retries after the first attempt, `None` retries until the peer is reachable.
+    pub retries: Option<u32>,
+    /// Delay before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Print a progress line to stderr for every failed attempt.
+    pub verbose: bool,
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `attempt` (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    pub(crate) fn should_retry(&self, attempt: u32, err: &Error) -> bool {
+        let retriable = matches!(err, Error::ConnectTimeout { .. } | Error::Connect { .. });
+        retriable && self.retries.is_none_or(|retries| attempt <= retries)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            connect_timeout: None,
+            retries: Some(0),
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            verbose: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_secs(1));
+        assert_eq!(policy.delay(4), Duration::from_secs(4));
+        assert_eq!(policy.delay(10), Duration::from_secs(30));
+        assert_eq!(policy.delay(100), Duration::from_secs(30));
+    }
+}
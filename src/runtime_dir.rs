@@ -0,0 +1,43 @@
+use std::{
+    io,
+    os::unix::fs::{DirBuilderExt as _, MetadataExt as _},
+    path::PathBuf,
+};
+
+/// The per-user directory local sockets live in, created with mode 0700.
+///
+/// `$XDG_RUNTIME_DIR/iroh-ssh` when set, `$TMPDIR/iroh-ssh-<uid>` otherwise.
+/// Directories owned by someone else or accessible to others are refused, so a
+/// local attacker can't pre-create the sockets.
+pub(crate) fn runtime_dir() -> io::Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("iroh-ssh"),
+        _ => std::env::temp_dir().join(format!("iroh-ssh-{uid}")),
+    };
+    private_dir(dir, uid)
+}
+
+pub(crate) fn private_dir(dir: PathBuf, uid: u32) -> io::Result<PathBuf> {
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    let meta = std::fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be a directory owned by you with mode 0700",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(dir)
+}
+
+/// A private subdirectory of [`runtime_dir`], created on demand.
+pub(crate) fn runtime_subdir(name: &str) -> io::Result<PathBuf> {
+    private_dir(runtime_dir()?.join(name), unsafe { libc::getuid() })
+}
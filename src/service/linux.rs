@@ -1,5 +1,4 @@
-use crate::Service;
-use crate::ServiceParams;
+use crate::{ServerOpts, Service};
 
 #[cfg(target_os = "linux")]
 #[derive(Debug, Clone)]
@@ -7,8 +6,8 @@ pub struct LinuxService;
 
 #[cfg(target_os = "linux")]
 impl Service for LinuxService {
-    async fn install(service_params: ServiceParams) -> anyhow::Result<()> {
-        let path = LinuxService::init_install_script(service_params)?;
+    async fn install(opts: ServerOpts) -> anyhow::Result<()> {
+        let path = LinuxService::init_install_script(&opts)?;
 
         runas::Command::new("sh")
             .arg(path)
@@ -41,19 +40,26 @@ impl LinuxService {
     const INSTALL_SH_BYTES: &str = include_str!("../../service/install_linux.sh");
     const UNINSTALL_SH_BYTES: &str = include_str!("../../service/uninstall_linux.sh");
 
-    fn init_install_script(service_params: ServiceParams) -> anyhow::Result<std::path::PathBuf> {
+    /// Where the install script copies the binary, the unit runs it from there.
+    const BINARY_PATH: &str = "/usr/local/bin/iroh-ssh";
+
+    fn init_install_script(opts: &ServerOpts) -> anyhow::Result<std::path::PathBuf> {
         use std::io::Write as _;
 
-        let mut server_args = String::new();
-        if let Some(ref dir) = service_params.key_dir {
-            server_args.push_str(&format!(" --key-dir {}", dir.display()));
-        }
-        for url in &service_params.relay_url {
-            server_args.push_str(&format!(" --relay-url {url}"));
-        }
-        for url in &service_params.extra_relay_url {
-            server_args.push_str(&format!(" --extra-relay-url {url}"));
-        }
+        let mut temp_unit = tempfile::Builder::new()
+            .prefix("iroh_ssh_unit-")
+            .suffix(".service")
+            .tempfile_in("/tmp")?;
+        temp_unit.write_all(Self::unit_file(opts).as_bytes())?;
+        let (_, unit_path) = temp_unit.keep()?;
+
+        let binary_path = std::env::current_exe()?;
+        let binary_path = binary_path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("failed to get current executable path"))?;
+        let unit_path = unit_path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("failed to get the unit file path"))?;
 
         let mut temp_sh = tempfile::Builder::new()
             .prefix("iroh_ssh_install-")
@@ -61,14 +67,8 @@ impl LinuxService {
             .tempfile_in("/tmp")?;
         temp_sh.write_all(
             LinuxService::INSTALL_SH_BYTES
-                .replace("[SSHPORT]", &service_params.ssh_port.to_string())
-                .replace("[SERVERARGS]", &server_args)
-                .replace(
-                    "[BINARYPATH]",
-                    std::env::current_exe()?
-                        .to_str()
-                        .ok_or_else(|| anyhow::anyhow!("failed to get current executable path"))?,
-                )
+                .replace("[UNITPATH]", &crate::quote::sh(unit_path))
+                .replace("[BINARYPATH]", &crate::quote::sh(binary_path))
                 .as_bytes(),
         )?;
         let sh_path = temp_sh.path().to_path_buf();
@@ -77,6 +77,30 @@ impl LinuxService {
         Ok(sh_path)
     }
 
+    /// The systemd unit running `iroh-ssh server -p` with `opts`, without a shell in between.
+    fn unit_file(opts: &ServerOpts) -> String {
+        let mut exec_start = format!("{} server -p", Self::BINARY_PATH);
+        for arg in opts.args() {
+            exec_start.push(' ');
+            exec_start.push_str(&systemd_quote(&arg));
+        }
+        format!(
+            "[Unit]
+Description=SSH over Iroh
+
+[Service]
+Type=simple
+WorkingDirectory=~
+ExecStart={exec_start}
+Restart=on-failure
+RestartSec=3s
+
+[Install]
+WantedBy=multi-user.target
+"
+        )
+    }
+
     fn init_uninstall_script() -> anyhow::Result<std::path::PathBuf> {
         use std::io::Write as _;
 
@@ -91,3 +115,95 @@ impl LinuxService {
         Ok(sh_path)
     }
 }
+
+/// Quotes `arg` for an `ExecStart=` line, which systemd splits like a shell but
+/// also expands `%` specifiers and `$` variables in.
+#[cfg(target_os = "linux")]
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty()
+        && escaped
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+*[]%$".contains(c))
+    {
+        return escaped;
+    }
+    let mut quoted = String::from("\"");
+    for c in escaped.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn install_opts(args: &[&str]) -> ServerOpts {
+        use clap::Parser as _;
+        let cli = crate::Cli::try_parse_from(["iroh-ssh", "service", "install"].iter().chain(args))
+            .unwrap();
+        match cli.cmd {
+            Some(crate::Cmd::Service {
+                op: crate::ServiceCmd::Install(opts),
+            }) => *opts,
+            other => panic!("not an install: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unit_file_quotes_exec_start() {
+        let opts = install_opts(&[
+            "--allow",
+            "nas=*",
+            "--advertise",
+            "--name",
+            "my \"host\"",
+            "--key-dir",
+            "/srv/100% keys",
+        ]);
+        let unit = LinuxService::unit_file(&opts);
+        assert!(
+            unit.contains(
+                "ExecStart=/usr/local/bin/iroh-ssh server -p --ssh-port 22 --key-dir \"/srv/100%% keys\" --allow nas=* --name \"my \\\"host\\\"\" --advertise\n"
+            ),
+            "{unit}"
+        );
+        assert_eq!(systemd_quote(""), "\"\"");
+        assert_eq!(systemd_quote("$HOME"), "$$HOME");
+    }
+
+    #[test]
+    fn server_opts_round_trip_through_args() {
+        let opts = install_opts(&[
+            "--ssh-host",
+            "localhost",
+            "--wait-for-sshd=30",
+            "--bench",
+            "--no-relay",
+            "--path",
+            "relay-only",
+            "--bind",
+            "41641",
+            "--ipv4-only",
+            "--quic-keep-alive",
+            "5",
+            "--peer-source-range",
+            "--target",
+            "web=127.0.0.1:8080",
+            "--allow",
+            "web=*",
+        ]);
+        let args = opts.args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        assert_eq!(install_opts(&args), opts);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::ServerOpts;
+
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
@@ -9,53 +11,35 @@ mod windows;
 pub(crate) use crate::service::windows::WindowsService;
 
 #[cfg(target_os = "windows")]
-pub async fn run_service(
-    ssh_port: u16,
-    key_dir: Option<std::path::PathBuf>,
-    relay_url: Vec<String>,
-    extra_relay_url: Vec<String>,
-) -> anyhow::Result<()> {
-    WindowsService::run_service(ServiceParams {
-        ssh_port,
-        key_dir: crate::api::abs_key_dir(key_dir),
-        relay_url,
-        extra_relay_url,
-    })
-    .await
+pub async fn run_service(opts: ServerOpts) -> anyhow::Result<()> {
+    WindowsService::run_service(absolute_paths(opts)?).await
 }
 
 #[cfg(not(target_os = "windows"))]
-pub async fn run_service(
-    _ssh_port: u16,
-    _key_dir: Option<std::path::PathBuf>,
-    _relay_url: Vec<String>,
-    _extra_relay_url: Vec<String>,
-) -> anyhow::Result<()> {
+pub async fn run_service(_opts: ServerOpts) -> anyhow::Result<()> {
     anyhow::bail!("service run is only supported on windows");
 }
 
-#[derive(Debug, Clone)]
-pub struct ServiceParams {
-    pub ssh_port: u16,
-    pub key_dir: Option<std::path::PathBuf>,
-    pub relay_url: Vec<String>,
-    pub extra_relay_url: Vec<String>,
+/// `opts` with the key dir and relay map made absolute, services don't start
+/// in the directory they were installed from.
+pub(crate) fn absolute_paths(mut opts: ServerOpts) -> anyhow::Result<ServerOpts> {
+    opts.key_dir = crate::api::abs_key_dir(opts.key_dir);
+    opts.relay_map = opts.relay_map.map(std::path::absolute).transpose()?;
+    Ok(opts)
 }
 
 pub trait Service {
-    fn install(
-        service_params: ServiceParams,
-    ) -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
+    fn install(opts: ServerOpts) -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
     fn info() -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
     fn uninstall() -> impl std::future::Future<Output = anyhow::Result<()>> + Send;
 }
 
-pub async fn install_service(_service_params: ServiceParams) -> anyhow::Result<()> {
+pub async fn install_service(_opts: ServerOpts) -> anyhow::Result<()> {
     match std::env::consts::OS {
         #[cfg(target_os = "linux")]
-        "linux" => LinuxService::install(_service_params).await,
+        "linux" => LinuxService::install(_opts).await,
         #[cfg(target_os = "windows")]
-        "windows" => WindowsService::install(_service_params).await,
+        "windows" => WindowsService::install(_opts).await,
         _ => anyhow::bail!("service mode is only supported on linux and windows"),
     }
 }
@@ -1,4 +1,4 @@
-use crate::{Service, ServiceParams};
+use crate::{ServerOpts, Service};
 
 use anyhow::{Context, anyhow, bail};
 
@@ -52,22 +52,14 @@ use windows_sys::Win32::{
 #[derive(Debug, Clone)]
 pub struct WindowsService;
 
+/// The options `run-service` was started with, for the service main the dispatcher calls.
 #[cfg(target_os = "windows")]
-static SERVICE_SSH_PORT: OnceLock<u16> = OnceLock::new();
-
-#[cfg(target_os = "windows")]
-static SERVICE_RELAY_URLS: OnceLock<Vec<String>> = OnceLock::new();
-
-#[cfg(target_os = "windows")]
-static SERVICE_EXTRA_RELAY_URLS: OnceLock<Vec<String>> = OnceLock::new();
-
-#[cfg(target_os = "windows")]
-static SERVICE_KEY_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+static SERVICE_OPTS: OnceLock<ServerOpts> = OnceLock::new();
 
 #[cfg(target_os = "windows")]
 impl Service for WindowsService {
-    async fn install(service_params: ServiceParams) -> anyhow::Result<()> {
-        task::spawn_blocking(move || WindowsService::install_blocking(service_params))
+    async fn install(opts: ServerOpts) -> anyhow::Result<()> {
+        task::spawn_blocking(move || WindowsService::install_blocking(opts))
             .await
             .context("windows service install task panicked")??;
         Ok(())
@@ -87,51 +79,27 @@ impl Service for WindowsService {
 
 #[cfg(target_os = "windows")]
 impl WindowsService {
-    pub async fn run_service(service_params: ServiceParams) -> anyhow::Result<()> {
-        task::spawn_blocking(move || WindowsService::run_service_dispatcher(service_params))
+    pub async fn run_service(opts: ServerOpts) -> anyhow::Result<()> {
+        task::spawn_blocking(move || WindowsService::run_service_dispatcher(opts))
             .await
             .context("windows service dispatcher task panicked")??;
         Ok(())
     }
 
-    fn run_service_dispatcher(service_params: ServiceParams) -> anyhow::Result<()> {
-        SERVICE_SSH_PORT
-            .set(service_params.ssh_port)
-            .ok()
-            .or_else(|| {
-                if SERVICE_SSH_PORT.get().copied() != Some(service_params.ssh_port) {
-                    None
-                } else {
-                    Some(())
-                }
-            })
-            .ok_or_else(|| anyhow!("service port already initialized with different value"))?;
-
-        let _ = SERVICE_KEY_DIR.set(service_params.key_dir);
-        let _ = SERVICE_RELAY_URLS.set(service_params.relay_url);
-        let _ = SERVICE_EXTRA_RELAY_URLS.set(service_params.extra_relay_url);
+    fn run_service_dispatcher(opts: ServerOpts) -> anyhow::Result<()> {
+        SERVICE_OPTS
+            .set(opts)
+            .map_err(|_| anyhow!("service options already initialized"))?;
 
         service_runtime::run().context("failed to start windows service dispatcher")?;
         Ok(())
     }
 
-    fn service_port() -> anyhow::Result<u16> {
-        SERVICE_SSH_PORT
+    fn service_opts() -> anyhow::Result<ServerOpts> {
+        SERVICE_OPTS
             .get()
-            .copied()
-            .ok_or_else(|| anyhow!("service port not initialized"))
-    }
-
-    fn service_relay_urls() -> Vec<String> {
-        SERVICE_RELAY_URLS.get().cloned().unwrap_or_default()
-    }
-
-    fn service_extra_relay_urls() -> Vec<String> {
-        SERVICE_EXTRA_RELAY_URLS.get().cloned().unwrap_or_default()
-    }
-
-    fn service_key_dir() -> Option<PathBuf> {
-        SERVICE_KEY_DIR.get().cloned().flatten()
+            .cloned()
+            .ok_or_else(|| anyhow!("service options not initialized"))
     }
 
     pub const SERVICE_NAME: &'static str = "iroh-ssh";
@@ -144,14 +112,14 @@ impl WindowsService {
     pub const SERVICE_PROFILE_ROOT: &'static str = r"C:\\Windows\\ServiceProfiles\\iroh-ssh";
     pub const SERVICE_SSH_DIR: &'static str = r"C:\\Windows\\ServiceProfiles\\iroh-ssh\\.ssh";
 
-    fn install_blocking(service_params: ServiceParams) -> anyhow::Result<()> {
+    fn install_blocking(opts: ServerOpts) -> anyhow::Result<()> {
         let staged_binary = Self::stage_binary().context("failed to stage service binary")?;
 
         tracing::info!("Adding Windows Firewall rules for service executable");
         firewall::add_firewall_rules(&staged_binary)
             .context("failed to add Windows Firewall rules - ensure running as administrator")?;
 
-        let service = Self::create_or_configure_service(&staged_binary, &service_params)
+        let service = Self::create_or_configure_service(&staged_binary, &opts)
             .context("failed to create or configure windows service")?;
 
         let service_sid = Self::lookup_service_sid().context("failed to resolve service SID")?;
@@ -211,7 +179,7 @@ impl WindowsService {
 
     fn create_or_configure_service(
         binary_path: &Path,
-        service_params: &ServiceParams,
+        opts: &ServerOpts,
     ) -> anyhow::Result<WinService> {
         let manager_access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
         let service_manager = ServiceManager::local_computer(None::<&str>, manager_access)
@@ -230,26 +198,10 @@ impl WindowsService {
             start_type: ServiceStartType::AutoStart,
             error_control: ServiceErrorControl::Normal,
             executable_path: binary_path.to_path_buf(),
-            launch_arguments: {
-                let mut args = vec![
-                    OsString::from("run-service"),
-                    OsString::from("--ssh-port"),
-                    OsString::from(service_params.ssh_port.to_string()),
-                ];
-                if let Some(ref dir) = service_params.key_dir {
-                    args.push(OsString::from("--key-dir"));
-                    args.push(OsString::from(dir));
-                }
-                for url in &service_params.relay_url {
-                    args.push(OsString::from("--relay-url"));
-                    args.push(OsString::from(url));
-                }
-                for url in &service_params.extra_relay_url {
-                    args.push(OsString::from("--extra-relay-url"));
-                    args.push(OsString::from(url));
-                }
-                args
-            },
+            launch_arguments: iter::once("run-service".to_string())
+                .chain(opts.args())
+                .map(OsString::from)
+                .collect(),
             dependencies: vec![ServiceDependency::Service(OsString::from(
                 Self::SERVICE_DEPENDENCY,
             ))],
@@ -577,12 +529,9 @@ mod service_runtime {
     fn run_service_worker() -> WinResult<()> {
         tracing::info!("run_service_worker: Starting");
 
-        let ssh_port = WindowsService::service_port().map_err(anyhow_to_win_error)?;
-        let key_dir = WindowsService::service_key_dir();
-        let relay_url = WindowsService::service_relay_urls();
-        let extra_relay_url = WindowsService::service_extra_relay_urls();
+        let opts = WindowsService::service_opts().map_err(anyhow_to_win_error)?;
 
-        tracing::info!("run_service_worker: SSH port = {}", ssh_port);
+        tracing::info!("run_service_worker: SSH port = {}", opts.ssh_port);
 
         let (shutdown_tx, shutdown_rx) = mpsc::channel();
         let event_handler = move |control_event| -> ServiceControlHandlerResult {
@@ -623,11 +572,8 @@ mod service_runtime {
 
             let result = crate::api::server_mode(
                 ServerArgs {
-                    ssh_port,
                     persist: true,
-                    key_dir,
-                    relay_url,
-                    extra_relay_url,
+                    opts,
                 },
                 true,
             )
@@ -1,21 +1,55 @@
-use crate::{Builder, Inner, IrohSsh, cli::SshOpts};
-use std::{ffi::OsString, io, path::Path, process::Stdio, time::Duration};
+use crate::{
+    AddrCache, Builder, CloseCode, CloseReason, DiscoveryBackend, EVENT_CHANNEL_CAPACITY, Error,
+    IpFamily, IrohSsh, LocalAdvert, PathPolicy, RetryPolicy, SessionBytes, SessionEvent,
+    TargetConnector, TargetRequest, Targets, TcpConnector, Tunnel,
+    bench::BenchServer,
+    bind::BindConfig,
+    cli::{ProxyOpts, SshOpts},
+    connector::DefaultConnector,
+    console,
+    discover::AdvertisingMdns,
+    health::SshdHealth,
+    peers::{self, SourceRange},
+    pump::{pump, pump_bidirectional},
+    target,
+    transport::{CongestionControl, QuicTuning},
+};
+use std::{
+    ffi::OsString,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
 
 use anyhow::bail;
 use ed25519_dalek::SECRET_KEY_LENGTH;
 use homedir::my_home;
 use regex::Regex;
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
 
 use iroh::{
-    Endpoint, EndpointId, RelayConfig, RelayUrl, SecretKey,
-    endpoint::{Connection, RelayMode},
-    protocol::{ProtocolHandler, Router},
+    Endpoint, EndpointAddr, EndpointId, RelayConfig, RelayMap, RelayUrl, SecretKey, TransportAddr,
+    Watcher as _,
+    discovery::{
+        dns::DnsDiscovery,
+        mdns::MdnsDiscovery,
+        pkarr::{PkarrPublisher, PkarrResolver},
+    },
+    endpoint::{Connection, ConnectionType, RecvStream, RelayMode, SendStream},
+    protocol::{ProtocolHandler, Router, RouterBuilder},
 };
+use n0_future::task::AbortOnDropHandle;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
     process::{Child, Command},
+    sync::broadcast,
+    task::JoinSet,
 };
 
 impl Builder {
@@ -24,9 +58,25 @@ impl Builder {
             secret_key: SecretKey::generate(&mut rand::rng()).to_bytes(),
             accept_incoming: false,
             accept_port: None,
+            accept_host: None,
+            wait_for_sshd: false,
+            bench: false,
+            bench_allow: Vec::new(),
             key_dir: None,
             relay_urls: Vec::new(),
             extra_relay_urls: Vec::new(),
+            relay_map: None,
+            local_only: false,
+            local_advert: None,
+            discovery: Vec::new(),
+            path_policy: PathPolicy::Any,
+            bind: BindConfig::default(),
+            quic: QuicTuning::default(),
+            source_range: None,
+            targets: Targets::default(),
+            target_connector: None,
+            max_sessions_per_peer: None,
+            addr_cache: None,
         }
     }
 
@@ -40,6 +90,21 @@ impl Builder {
         self
     }
 
+    /// Dials sshd on `host` instead of 127.0.0.1, for both the availability check and tunnels.
+    pub fn accept_host(mut self, host: IpAddr) -> Self {
+        self.accept_host = Some(host);
+        self
+    }
+
+    /// Starts even when sshd is down and keeps probing it in the background,
+    /// tunnels are closed with [`CloseCode::TargetUnavailable`] while it's unreachable.
+    ///
+    /// See [`IrohSsh::wait_for_sshd`] to give up when it doesn't come up.
+    pub fn wait_for_sshd(mut self, wait: bool) -> Self {
+        self.wait_for_sshd = wait;
+        self
+    }
+
     pub fn secret_key(mut self, secret_key: &[u8; SECRET_KEY_LENGTH]) -> Self {
         self.secret_key = *secret_key;
         self
@@ -55,6 +120,177 @@ impl Builder {
         self
     }
 
+    /// Uses the relays in `relay_map` with their own options, see [`crate::load_relay_map`].
+    ///
+    /// Takes precedence over [`Builder::relay_urls`] and [`Builder::extra_relay_urls`].
+    pub fn relay_map(mut self, relay_map: RelayMap) -> Self {
+        self.relay_map = Some(relay_map);
+        self
+    }
+
+    /// The relays the built endpoint will use.
+    pub fn relays(&self) -> RelayMap {
+        self.relay_mode()
+            .unwrap_or_else(iroh::endpoint::default_relay_mode)
+            .relay_map()
+    }
+
+    /// The relay mode to bind with, `None` keeps iroh's default.
+    fn relay_mode(&self) -> Option<RelayMode> {
+        if self.local_only || self.path_policy == PathPolicy::DirectOnly {
+            Some(RelayMode::Disabled)
+        } else if let Some(relay_map) = &self.relay_map {
+            Some(RelayMode::Custom(relay_map.clone()))
+        } else if !self.relay_urls.is_empty() {
+            Some(RelayMode::Custom(self.relay_urls.iter().cloned().collect()))
+        } else if !self.extra_relay_urls.is_empty() {
+            let relay_map = RelayMode::Default.relay_map();
+            for url in &self.extra_relay_urls {
+                relay_map.insert(url.clone(), Arc::new(RelayConfig::from(url.clone())));
+            }
+            Some(RelayMode::Custom(relay_map))
+        } else {
+            None
+        }
+    }
+
+    /// Disables relays and internet discovery, peers are found via mDNS on the
+    /// local network or dialed at the direct addresses they are given with.
+    pub fn local_only(mut self, local_only: bool) -> Self {
+        self.local_only = local_only;
+        self
+    }
+
+    /// Announces `advert` via mDNS so `iroh-ssh discover` lists this endpoint.
+    pub fn advertise_local(mut self, advert: LocalAdvert) -> Self {
+        self.local_advert = Some(advert);
+        self
+    }
+
+    /// Replaces the default discovery (public DNS, or mDNS with [`Builder::local_only`])
+    /// with `backends`. An advertised endpoint always keeps mDNS.
+    pub fn discovery(mut self, backends: Vec<DiscoveryBackend>) -> Self {
+        self.discovery = backends;
+        self
+    }
+
+    /// Also answers `iroh-ssh bench` on [`IrohSsh::BENCH_ALPN`], from any peer unless
+    /// [`Builder::bench_allow`] narrows it down.
+    ///
+    /// Benchmarks count against [`Builder::max_sessions_per_peer`] and are closed on shutdown
+    /// like tunnels.
+    pub fn bench(mut self, bench: bool) -> Self {
+        self.bench = bench;
+        self
+    }
+
+    /// Only answers `iroh-ssh bench` from `peers`, others are closed with
+    /// [`CloseCode::NotAuthorized`]. Empty allows every peer.
+    pub fn bench_allow(mut self, peers: Vec<EndpointId>) -> Self {
+        self.bench_allow = peers;
+        self
+    }
+
+    /// Restricts connections to direct or relayed paths, others are closed.
+    ///
+    /// [`PathPolicy::DirectOnly`] also disables relays on the endpoint, [`PathPolicy::RelayOnly`]
+    /// binds its UDP sockets to loopback instead of [`Builder::bind_addr`].
+    pub fn path_policy(mut self, policy: PathPolicy) -> Self {
+        self.path_policy = policy;
+        self.bind.loopback_only = policy == PathPolicy::RelayOnly;
+        self
+    }
+
+    /// Binds the endpoint's UDP socket of `addr`'s family there instead of a random port.
+    ///
+    /// Building fails with [`Error::BindAddrUnavailable`] when the address is taken.
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(addr) => self.bind.v4 = Some(addr),
+            SocketAddr::V6(addr) => self.bind.v6 = Some(addr),
+        }
+        self
+    }
+
+    /// Talks to peers over IPv4 or IPv6 only, e.g. on hosts with broken IPv6.
+    pub fn ip_family(mut self, family: IpFamily) -> Self {
+        self.bind.family = family;
+        self
+    }
+
+    /// How many bytes a peer may send on one stream before it's read.
+    ///
+    /// Tunnels run one stream each, so this caps a transfer at window/RTT on
+    /// long links: raise it on the receiving side, the server for uploads.
+    /// The QUIC options only apply to [`Builder::build`], not to [`Builder::build_on`].
+    pub fn stream_receive_window(mut self, bytes: u64) -> Self {
+        self.quic.stream_receive_window = Some(bytes);
+        self
+    }
+
+    /// How many bytes a peer may send over all streams of a connection before they're read.
+    pub fn receive_window(mut self, bytes: u64) -> Self {
+        self.quic.receive_window = Some(bytes);
+        self
+    }
+
+    /// How many bytes are buffered for sending per connection, raise it with the peer's receive windows.
+    pub fn send_window(mut self, bytes: u64) -> Self {
+        self.quic.send_window = Some(bytes);
+        self
+    }
+
+    /// The congestion controller sending data with, see [`CongestionControl`] (default: cubic).
+    pub fn congestion_control(mut self, congestion: CongestionControl) -> Self {
+        self.quic.congestion = Some(congestion);
+        self
+    }
+
+    /// Pings idle connections this often, `None` turns keep-alives off (default: 1s).
+    pub fn keep_alive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.quic.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Drops connections the peer hasn't answered for this long, `None` never does (default: 30s).
+    ///
+    /// The lower of both peers' timeouts applies.
+    pub fn max_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.quic.max_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Dial the local ssh server from a per-peer address in `range` instead of 127.0.0.1.
+    pub fn source_range(mut self, range: SourceRange) -> Self {
+        self.source_range = Some(range);
+        self
+    }
+
+    /// Named targets on the server's network that allowed peers can be forwarded to.
+    pub fn targets(mut self, targets: Targets) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// Routes accepted tunnels through `connector` instead of the local sshd and [`Targets`].
+    pub fn target_connector(mut self, connector: impl TargetConnector) -> Self {
+        self.target_connector = Some(Arc::new(connector));
+        self
+    }
+
+    /// Resets new tunnels from a peer with [`CloseCode::RateLimited`] while it already has `max` open,
+    /// counting every tunnel on every connection, and refuses its new connections meanwhile.
+    pub fn max_sessions_per_peer(mut self, max: usize) -> Self {
+        self.max_sessions_per_peer = Some(max);
+        self
+    }
+
+    /// Seeds outgoing connections from `cache` and refreshes it after each successful connect.
+    pub fn addr_cache(mut self, cache: AddrCache) -> Self {
+        self.addr_cache = Some(cache);
+        self
+    }
+
     pub fn key_dir(mut self, key_dir: Option<std::path::PathBuf>) -> Self {
         self.key_dir = key_dir;
         self
@@ -89,51 +325,143 @@ impl Builder {
         self
     }
 
-    pub async fn build(&mut self) -> anyhow::Result<IrohSsh> {
+    pub async fn build(&mut self) -> Result<IrohSsh, Error> {
         // Iroh setup
         let secret_key = SecretKey::from_bytes(&self.secret_key);
-        let mut builder = Endpoint::builder().secret_key(secret_key);
-
-        if !self.relay_urls.is_empty() {
-            let relay_map = self.relay_urls.iter().cloned().collect();
-            builder = builder.relay_mode(RelayMode::Custom(relay_map));
-        } else if !self.extra_relay_urls.is_empty() {
-            let relay_map = RelayMode::Default.relay_map();
-            for url in &self.extra_relay_urls {
-                relay_map.insert(url.clone(), Arc::new(RelayConfig::from(url.clone())));
-            }
-            builder = builder.relay_mode(RelayMode::Custom(relay_map));
+        let mut builder = if self.local_only {
+            Endpoint::empty_builder(RelayMode::Disabled)
+        } else {
+            Endpoint::builder()
+        }
+        .secret_key(secret_key)
+        .bind_addr_v4(self.bind.v4_addr());
+        if let Some(addr) = self.bind.v6_addr() {
+            builder = builder.bind_addr_v6(addr);
+        }
+        let backends: &[DiscoveryBackend] = if !self.discovery.is_empty() {
+            builder = builder.clear_discovery();
+            self.discovery.as_slice()
+        } else if self.local_only {
+            &[DiscoveryBackend::Local]
+        } else {
+            &[]
+        };
+        for backend in backends {
+            builder = match backend {
+                DiscoveryBackend::Dns => builder
+                    .discovery(PkarrPublisher::n0_dns())
+                    .discovery(DnsDiscovery::n0_dns()),
+                DiscoveryBackend::Pkarr(url) => builder
+                    .discovery(PkarrPublisher::builder(url.clone()))
+                    .discovery(PkarrResolver::builder(url.clone())),
+                DiscoveryBackend::Local if self.local_advert.is_none() => {
+                    builder.discovery(MdnsDiscovery::builder().advertise(self.accept_incoming))
+                }
+                DiscoveryBackend::Local | DiscoveryBackend::None => builder,
+            };
+        }
+        if let Some(advert) = &self.local_advert {
+            builder = builder.discovery(AdvertisingMdns::builder(advert));
         }
 
-        let endpoint = builder.bind().await?;
+        if let Some(relay_mode) = self.relay_mode() {
+            builder = builder.relay_mode(relay_mode);
+        }
+        if let Some(transport_config) = self.quic.transport_config() {
+            builder = builder.transport_config(transport_config);
+        }
 
-        let mut iroh_ssh = IrohSsh {
-            public_key: *endpoint.id().as_bytes(),
-            secret_key: self.secret_key,
-            inner: None,
-            ssh_port: self.accept_port.unwrap_or(22),
-        };
+        let endpoint = builder
+            .bind()
+            .await
+            .map_err(|e| Error::EndpointBind(Box::new(e)))?;
+        if let Some(addr) = self.bind.missing(&endpoint.bound_sockets()) {
+            endpoint.close().await;
+            return Err(Error::BindAddrUnavailable { addr });
+        }
+        let mut iroh_ssh = self.build_on(endpoint.clone()).await?;
 
         let router = if self.accept_incoming {
-            if is_ssh_server_available(iroh_ssh.ssh_port, Duration::from_secs(10)).await.is_err() {
-                eprintln!("SSH server not available on port {}, incoming connections will fail. Please ensure you have an SSH server installed and running on port {}.", iroh_ssh.ssh_port, iroh_ssh.ssh_port);
-                bail!("no ssh server available on specified port")
-            }
-            Router::builder(endpoint.clone()).accept(IrohSsh::ALPN(), iroh_ssh.clone())
+            iroh_ssh.accept_on(Router::builder(endpoint))
         } else {
-            Router::builder(endpoint.clone())
+            Router::builder(endpoint)
         }
         .spawn();
-
-        iroh_ssh.add_inner(endpoint, router);
+        iroh_ssh.router = Some(router);
 
         Ok(iroh_ssh)
     }
+
+    /// Builds on an endpoint the caller already runs, e.g. next to other ALPNs.
+    ///
+    /// No router is spawned: register the returned [`IrohSsh`] on your own
+    /// router with [`IrohSsh::accept_on`] to serve incoming tunnels.
+    pub async fn build_on(&mut self, endpoint: Endpoint) -> Result<IrohSsh, Error> {
+        let ssh_addr = SocketAddr::new(
+            self.accept_host.unwrap_or(Ipv4Addr::LOCALHOST.into()),
+            self.accept_port.unwrap_or(22),
+        );
+        let mut sshd_health = None;
+        let connector = match &self.target_connector {
+            Some(connector) => connector.clone(),
+            None => {
+                if self.accept_incoming && self.wait_for_sshd {
+                    sshd_health = Some(SshdHealth::spawn(ssh_addr));
+                } else if self.accept_incoming
+                    && is_ssh_server_available(ssh_addr, Duration::from_secs(10))
+                        .await
+                        .is_err()
+                {
+                    eprintln!(
+                        "SSH server not available on {ssh_addr}, incoming connections will fail. Please ensure you have an SSH server installed and running on {ssh_addr}."
+                    );
+                    return Err(Error::SshdUnavailable { addr: ssh_addr });
+                }
+
+                let mut ssh = TcpConnector::new(ssh_addr);
+                if let Some(range) = self.source_range {
+                    let ssh_dir = match &self.key_dir {
+                        Some(dir) => Some(dir.clone()),
+                        None => my_home().ok().flatten().map(|home| home.join(".ssh")),
+                    };
+                    ssh = ssh.source_range(range).peers_file(
+                        ssh_dir
+                            .filter(|dir| dir.exists())
+                            .map(|dir| peers::peers_file(&dir)),
+                    );
+                }
+                Arc::new(DefaultConnector {
+                    ssh,
+                    targets: Arc::new(self.targets.clone()),
+                    health: sshd_health.clone(),
+                })
+            }
+        };
+
+        Ok(IrohSsh {
+            public_key: *endpoint.id().as_bytes(),
+            secret_key: endpoint.secret_key().to_bytes(),
+            endpoint,
+            router: None,
+            connector,
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            sessions: Arc::default(),
+            max_sessions_per_peer: self.max_sessions_per_peer,
+            addr_cache: self.addr_cache.clone(),
+            path_policy: self.path_policy,
+            sshd_health,
+            bench: self.bench,
+            bench_allow: Arc::new(self.bench_allow.clone()),
+        })
+    }
 }
 
-async fn is_ssh_server_available(port: u16, timeout: Duration) -> anyhow::Result<()> {
+pub(crate) async fn is_ssh_server_available(
+    addr: SocketAddr,
+    timeout: Duration,
+) -> anyhow::Result<()> {
     tokio::time::timeout(timeout, async {
-        let stream = TcpStream::connect(format!("localhost:{port}")).await?;
+        let stream = TcpStream::connect(addr).await?;
         let mut reader = BufReader::new(stream);
         let mut line_buf = String::new();
         let regex = Regex::new(r"^SSH-\d+\.\d+-").expect("valid regex");
@@ -168,8 +496,49 @@ impl IrohSsh {
         b"/iroh/ssh".to_vec()
     }
 
-    fn add_inner(&mut self, endpoint: Endpoint, router: Router) {
-        self.inner = Some(Inner { endpoint, router });
+    /// Like [`IrohSsh::ALPN`], but the client names the server-side target first.
+    #[allow(non_snake_case)]
+    pub fn TARGET_ALPN() -> Vec<u8> {
+        b"/iroh/ssh/target".to_vec()
+    }
+
+    /// Registers the ssh and target ALPNs on `router`, and the benchmark ALPN if enabled.
+    pub fn accept_on(&self, router: RouterBuilder) -> RouterBuilder {
+        let router = router
+            .accept(IrohSsh::ALPN(), self.clone())
+            .accept(IrohSsh::TARGET_ALPN(), self.clone());
+        if self.bench {
+            router.accept(IrohSsh::BENCH_ALPN(), BenchServer::new(self.clone()))
+        } else {
+            router
+        }
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Subscribes to [`SessionEvent`]s for tunnels accepted from now on.
+    ///
+    /// Events are dropped for subscribers that fall more than
+    /// [`EVENT_CHANNEL_CAPACITY`] events behind.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Resolves once the local sshd answers, or fails with [`Error::SshdUnavailable`] after `timeout`.
+    ///
+    /// Returns right away unless built with [`Builder::wait_for_sshd`], sshd was checked then.
+    pub async fn wait_for_sshd(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        match &self.sshd_health {
+            Some(health) => health.wait(timeout).await,
+            None => Ok(()),
+        }
+    }
+
+    /// The router spawned by [`Builder::build`], `None` when built with [`Builder::build_on`].
+    pub fn router(&self) -> Option<&Router> {
+        self.router.as_ref()
     }
 
     pub async fn start_ssh(
@@ -179,8 +548,14 @@ impl IrohSsh {
         remote_cmd: Vec<OsString>,
         relay_urls: &[String],
         extra_relay_urls: &[String],
-    ) -> io::Result<Child> {
-        let c_exe = std::env::current_exe()?;
+        proxy_opts: &ProxyOpts,
+    ) -> Result<Child, Error> {
+        let c_exe = std::env::current_exe().map_err(Error::SshSpawn)?;
+        let control_path = if ssh_opts.mux {
+            mux_control_path(proxy_opts)
+        } else {
+            None
+        };
         let mut cmd = build_ssh_command(
             &c_exe,
             target,
@@ -188,56 +563,240 @@ impl IrohSsh {
             remote_cmd,
             relay_urls,
             extra_relay_urls,
+            proxy_opts,
+            control_path.as_deref(),
         );
 
         let ssh_process = cmd
             .stdin(Stdio::inherit())
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit())
-            .spawn()?;
+            .spawn()
+            .map_err(Error::SshSpawn)?;
 
         Ok(ssh_process)
     }
 
-    pub async fn connect_pubkey(&self, endpoint_id: EndpointId) -> anyhow::Result<()> {
-        let inner = self.inner.as_ref().expect("inner not set");
-        let conn = inner
+    /// Opens a tunnel to `request` on the server at `endpoint_addr`.
+    pub async fn open(
+        &self,
+        endpoint_addr: impl Into<EndpointAddr>,
+        request: &TargetRequest,
+    ) -> Result<Tunnel, Error> {
+        let mut endpoint_addr = endpoint_addr.into();
+        let endpoint_id = endpoint_addr.id;
+        if let Some(cached) = self.addr_cache.as_ref().and_then(|c| c.get(&endpoint_id)) {
+            endpoint_addr = endpoint_addr.with_addrs(cached.addrs);
+        }
+        if self.path_policy == PathPolicy::RelayOnly {
+            // don't hand iroh direct addresses to start on
+            endpoint_addr = EndpointAddr::from_parts(
+                endpoint_id,
+                endpoint_addr
+                    .addrs
+                    .into_iter()
+                    .filter(|addr| matches!(addr, TransportAddr::Relay(_))),
+            );
+        }
+        let alpn = match request {
+            TargetRequest::Ssh => IrohSsh::ALPN(),
+            TargetRequest::Named(_) => IrohSsh::TARGET_ALPN(),
+        };
+        let conn = self
             .endpoint
-            .connect(endpoint_id, &IrohSsh::ALPN())
+            .connect(endpoint_addr, &alpn)
+            .await
+            .map_err(|e| Error::from_connect(endpoint_id, e))?;
+        let path_watch = self.cache_path(endpoint_id);
+        let tunnel = Self::open_on(conn, request).await?;
+        Ok(match path_watch {
+            Some(path_watch) => tunnel.with_path_watch(path_watch),
+            None => tunnel,
+        })
+    }
+
+    /// Caches the path to `endpoint_id` now, for its relay, and again once hole
+    /// punching turns it direct. The task watches until it's dropped.
+    fn cache_path(&self, endpoint_id: EndpointId) -> Option<AbortOnDropHandle<()>> {
+        let cache = self.addr_cache.clone()?;
+        let mut watcher = self.endpoint.conn_type(endpoint_id)?;
+        let record = move |path| {
+            if let Err(e) = cache.record(endpoint_id, path) {
+                tracing::warn!("failed to update {}: {e}", cache.path().display());
+            }
+        };
+        let path = watcher.get();
+        let direct = matches!(path, ConnectionType::Direct(_));
+        record(path);
+        if direct {
+            return None;
+        }
+        Some(AbortOnDropHandle::new(tokio::spawn(async move {
+            while let Ok(path) = watcher.updated().await {
+                if matches!(path, ConnectionType::Direct(_)) {
+                    record(path);
+                    break;
+                }
+            }
+        })))
+    }
+
+    /// Opens another tunnel on an established connection, see [`Tunnel::connection`].
+    ///
+    /// The connection's ALPN must match `request`: [`IrohSsh::ALPN`] for
+    /// [`TargetRequest::Ssh`], [`IrohSsh::TARGET_ALPN`] for named targets.
+    pub async fn open_on(conn: Connection, request: &TargetRequest) -> Result<Tunnel, Error> {
+        let endpoint_id = conn.remote_id().map_err(io::Error::other)?;
+        let (mut iroh_send, iroh_recv) = conn
+            .open_bi()
+            .await
+            .map_err(|e| Error::from_connection(endpoint_id, e))?;
+        if let TargetRequest::Named(name) = request {
+            target::write_target_name(&mut iroh_send, name).await?;
+        }
+        Ok(Tunnel::new(conn, iroh_send, iroh_recv))
+    }
+
+    /// Like [`IrohSsh::open`], but times out and retries unreachable peers per `policy`.
+    pub async fn open_with_retry(
+        &self,
+        endpoint_addr: impl Into<EndpointAddr>,
+        request: &TargetRequest,
+        policy: &RetryPolicy,
+    ) -> Result<Tunnel, Error> {
+        let endpoint_addr = endpoint_addr.into();
+        let endpoint_id = endpoint_addr.id;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if policy.verbose {
+                eprintln!("iroh-ssh: connecting to {endpoint_id} (attempt {attempt})");
+            }
+            let res = match policy.connect_timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(timeout, self.open(endpoint_addr.clone(), request))
+                        .await
+                        .unwrap_or(Err(Error::ConnectTimeout { endpoint_id }))
+                }
+                None => self.open(endpoint_addr.clone(), request).await,
+            };
+            match res {
+                Err(err) if policy.should_retry(attempt, &err) => {
+                    let delay = policy.delay(attempt);
+                    if policy.verbose {
+                        eprintln!("iroh-ssh: {err}, retrying in {delay:?}");
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+                Ok(tunnel) => {
+                    if policy.verbose {
+                        eprintln!("iroh-ssh: connected to {endpoint_id}");
+                    }
+                    return Ok(tunnel);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Proxies stdin/stdout through a tunnel, as ssh's ProxyCommand.
+    pub async fn connect_pubkey(
+        &self,
+        endpoint_addr: impl Into<EndpointAddr>,
+        target_name: Option<&str>,
+        policy: &RetryPolicy,
+    ) -> Result<(), Error> {
+        let endpoint_addr = endpoint_addr.into();
+        let endpoint_id = endpoint_addr.id;
+        let request = match target_name {
+            Some(name) => TargetRequest::Named(name.to_string()),
+            None => TargetRequest::Ssh,
+        };
+        let mut tunnel = self
+            .open_with_retry(endpoint_addr, &request, policy)
             .await?;
-        let (mut iroh_send, mut iroh_recv) = conn.open_bi().await?;
-        let (mut local_read, mut local_write) = (tokio::io::stdin(), tokio::io::stdout());
-        let a_to_b = async move {
-            let res = tokio::io::copy(&mut local_read, &mut iroh_send).await;
-            iroh_send.finish().ok();
-            res
+        let mut stdio = tokio::io::join(tokio::io::stdin(), tokio::io::stdout());
+        let violation = tokio::select! {
+            _ = pump_bidirectional(&mut stdio, &mut tunnel) => None,
+            path = self.path_policy.violation(&self.endpoint, endpoint_id) => Some(path),
         };
-        let b_to_a = async move { tokio::io::copy(&mut iroh_recv, &mut local_write).await };
+        if let Some(path) = violation {
+            CloseCode::PathNotAllowed.close(tunnel.connection());
+            return Err(Error::PathNotAllowed {
+                endpoint_id,
+                path,
+                policy: self.path_policy,
+            });
+        }
+        match tunnel.close_code() {
+            Some(code) => Err(Error::TunnelClosed { endpoint_id, code }),
+            None => Ok(()),
+        }
+    }
 
-        let (_, _) = tokio::join!(a_to_b, b_to_a);
-        Ok(())
+    /// Attaches the local terminal to a unix socket or tty target on the server.
+    pub async fn console(
+        &self,
+        endpoint_addr: impl Into<EndpointAddr>,
+        target_name: &str,
+    ) -> Result<(), Error> {
+        let tunnel = self
+            .open(
+                endpoint_addr,
+                &TargetRequest::Named(target_name.to_string()),
+            )
+            .await?;
+        Ok(console::attach(tunnel).await?)
     }
 
-    pub async fn connect_tcpip(&self, host_addr: &str) -> anyhow::Result<()> {
+    pub async fn connect_tcpip(&self, host_addr: &str) -> Result<(), Error> {
         let conn = tokio::net::TcpStream::connect(host_addr).await?;
         let (mut tcp_read, mut tcp_write) = conn.into_split();
         let (mut local_read, mut local_write) = (tokio::io::stdin(), tokio::io::stdout());
         let a_to_b = async move {
-            let res = tokio::io::copy(&mut local_read, &mut tcp_write).await;
+            let res = pump(&mut local_read, &mut tcp_write).await;
             tcp_write.shutdown().await.ok();
             res
         };
-        let b_to_a = async move { tokio::io::copy(&mut tcp_read, &mut local_write).await };
+        let b_to_a = async move { pump(&mut tcp_read, &mut local_write).await };
 
         let (_, _) = tokio::join!(a_to_b, b_to_a);
         Ok(())
     }
 
     pub fn endpoint_id(&self) -> EndpointId {
-        self.inner.as_ref().expect("inner not set").endpoint.id()
+        self.endpoint.id()
+    }
+
+    /// The endpoint id with the relay and direct addresses currently known for this endpoint.
+    pub fn endpoint_addr(&self) -> EndpointAddr {
+        self.endpoint.addr()
     }
 }
 
+#[cfg(unix)]
+fn mux_control_path(proxy_opts: &ProxyOpts) -> Option<PathBuf> {
+    match crate::mux::control_path(proxy_opts.target_name.as_deref()) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            eprintln!("warning: not sharing the tunnel, {e}");
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn mux_control_path(_proxy_opts: &ProxyOpts) -> Option<PathBuf> {
+    eprintln!("warning: --mux is only supported on unix");
+    None
+}
+
+/// Quotes `arg` for a `ProxyCommand`, which ssh expands `%` tokens in and then runs with `/bin/sh -c`.
+fn proxy_word(arg: &str) -> String {
+    crate::quote::sh(arg).replace('%', "%%")
+}
+
+#[allow(clippy::too_many_arguments)]
 fn build_ssh_command(
     iroh_ssh_exe: &Path,
     target: String,
@@ -245,18 +804,74 @@ fn build_ssh_command(
     remote_cmd: Vec<OsString>,
     relay_urls: &[String],
     extra_relay_urls: &[String],
+    proxy_opts: &ProxyOpts,
+    control_path: Option<&Path>,
 ) -> Command {
     let mut cmd = Command::new("ssh");
 
-    let mut proxy_cmd = format!("{} proxy", iroh_ssh_exe.display());
+    let mut proxy_cmd = format!("{} proxy", proxy_word(&iroh_ssh_exe.to_string_lossy()));
     for url in relay_urls {
-        proxy_cmd.push_str(&format!(" --relay-url {url}"));
+        proxy_cmd.push_str(&format!(" --relay-url {}", proxy_word(url)));
     }
     for url in extra_relay_urls {
-        proxy_cmd.push_str(&format!(" --extra-relay-url {url}"));
+        proxy_cmd.push_str(&format!(" --extra-relay-url {}", proxy_word(url)));
+    }
+    if let Some(name) = &proxy_opts.target_name {
+        proxy_cmd.push_str(&format!(" --target-name {}", proxy_word(name)));
+    }
+    if let Some(secs) = proxy_opts.connect_timeout {
+        proxy_cmd.push_str(&format!(" --connect-timeout {secs}"));
+    }
+    if proxy_opts.retries > 0 {
+        proxy_cmd.push_str(&format!(" --retries {}", proxy_opts.retries));
+    }
+    if proxy_opts.wait_for_host {
+        proxy_cmd.push_str(" --wait-for-host");
+    }
+    if proxy_opts.no_relay {
+        proxy_cmd.push_str(" --no-relay");
+    }
+    if let Some(path) = &proxy_opts.relay_map {
+        proxy_cmd.push_str(&format!(
+            " --relay-map {}",
+            proxy_word(&path.to_string_lossy())
+        ));
+    }
+    if proxy_opts.path != PathPolicy::Any {
+        proxy_cmd.push_str(&format!(" --path {}", proxy_opts.path));
+    }
+    for backend in &proxy_opts.discovery {
+        proxy_cmd.push_str(&format!(
+            " --discovery {}",
+            proxy_word(&backend.to_string())
+        ));
+    }
+    for addr in &proxy_opts.addrs {
+        proxy_cmd.push_str(&format!(" --addr {addr}"));
+    }
+    for addr in &proxy_opts.bind.bind_addrs {
+        proxy_cmd.push_str(&format!(" --bind {addr}"));
+    }
+    if proxy_opts.bind.ipv4_only {
+        proxy_cmd.push_str(" --ipv4-only");
+    }
+    if proxy_opts.bind.ipv6_only {
+        proxy_cmd.push_str(" --ipv6-only");
+    }
+    for arg in proxy_opts.quic.args() {
+        proxy_cmd.push_str(&format!(" {arg}"));
+    }
+    if ssh_opts.verbose > 0 {
+        proxy_cmd.push_str(" --verbose");
     }
     proxy_cmd.push_str(" %h:%p");
     cmd.arg("-o").arg(format!("ProxyCommand={proxy_cmd}"));
+    #[cfg(unix)]
+    if let Some(control_path) = control_path {
+        crate::mux::add_control_opts(&mut cmd, control_path, ssh_opts.mux_persist.as_deref());
+    }
+    #[cfg(not(unix))]
+    let _ = control_path;
 
     if let Some(p) = ssh_opts.port {
         cmd.arg("-p").arg(p.to_string());
@@ -315,59 +930,310 @@ fn build_ssh_command(
 impl ProtocolHandler for IrohSsh {
     async fn accept(&self, connection: Connection) -> Result<(), iroh::protocol::AcceptError> {
         let endpoint_id = connection.remote_id()?;
+        let started = Instant::now();
+        self.emit(SessionEvent::PeerConnected {
+            remote: endpoint_id,
+        });
+
+        if let Err(code) = self.admit(&connection, endpoint_id) {
+            println!("Rejecting {endpoint_id}: {}", code.reason());
+            code.close(&connection);
+            self.emit(SessionEvent::SessionClosed {
+                remote: endpoint_id,
+                bytes: SessionBytes::default(),
+                duration: started.elapsed(),
+                reason: match code {
+                    CloseCode::ShuttingDown => CloseReason::ShuttingDown,
+                    _ => CloseReason::RateLimited,
+                },
+            });
+            return Ok(());
+        }
+
+        let path_violated = Arc::new(OnceLock::new());
+        let path_watch = self.endpoint.conn_type(endpoint_id).map(|mut watcher| {
+            let events = self.events.clone();
+            let policy = self.path_policy;
+            let connection = connection.clone();
+            let path_violated = path_violated.clone();
+            tokio::spawn(async move {
+                let mut last = watcher.get();
+                loop {
+                    if !policy.allows(&last) {
+                        println!("Closing {endpoint_id}: {last} is not allowed by --path {policy}");
+                        path_violated.set(()).ok();
+                        CloseCode::PathNotAllowed.close(&connection);
+                        break;
+                    }
+                    let Ok(path) = watcher.updated().await else {
+                        break;
+                    };
+                    if path != last {
+                        events
+                            .send(SessionEvent::PathChanged {
+                                remote: endpoint_id,
+                                path: path.clone(),
+                            })
+                            .ok();
+                        last = path;
+                    }
+                }
+            })
+        });
+        let close_reason = move |reason| match path_violated.get() {
+            Some(()) => CloseReason::PathNotAllowed,
+            None => reason,
+        };
 
-        match connection.accept_bi().await {
-            Ok((mut iroh_send, mut iroh_recv)) => {
-                println!("Accepted bidirectional stream from {endpoint_id}");
+        // Clients may open several tunnels on one connection, e.g. through `iroh-ssh agent`.
+        let mut streams = JoinSet::new();
+        let mut served = false;
+        loop {
+            let (mut iroh_send, mut iroh_recv) = match connection.accept_bi().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    if !served {
+                        println!("Failed to accept bidirectional stream: {e}");
+                        self.emit(SessionEvent::SessionClosed {
+                            remote: endpoint_id,
+                            bytes: SessionBytes::default(),
+                            duration: started.elapsed(),
+                            reason: close_reason(CloseReason::ConnectionLost(e.to_string())),
+                        });
+                    }
+                    break;
+                }
+            };
+            let slot = match self.open_stream(endpoint_id) {
+                Ok(slot) => slot,
+                Err(code) => {
+                    println!("Rejecting tunnel from {endpoint_id}: {}", code.reason());
+                    code.reset(&mut iroh_send, &mut iroh_recv);
+                    self.emit(SessionEvent::SessionClosed {
+                        remote: endpoint_id,
+                        bytes: SessionBytes::default(),
+                        duration: Duration::ZERO,
+                        reason: match code {
+                            CloseCode::ShuttingDown => CloseReason::ShuttingDown,
+                            _ => CloseReason::RateLimited,
+                        },
+                    });
+                    continue;
+                }
+            };
+            let this = self.clone();
+            let connection = connection.clone();
+            let close_reason = close_reason.clone();
+            streams.spawn(async move {
+                let _slot = slot;
+                let started = Instant::now();
+                let (bytes, reason) = this
+                    .serve(&connection, endpoint_id, iroh_send, iroh_recv)
+                    .await;
+                this.emit(SessionEvent::SessionClosed {
+                    remote: endpoint_id,
+                    bytes,
+                    duration: started.elapsed(),
+                    reason: close_reason(reason),
+                });
+            });
+            served = true;
+            while streams.try_join_next().is_some() {}
+        }
+        streams.join_all().await;
 
-                match TcpStream::connect(format!("127.0.0.1:{}", self.ssh_port)).await {
-                    Ok(mut ssh_stream) => {
-                        println!("Connected to local SSH server on port {}", self.ssh_port);
+        self.release(&connection);
+        if let Some(path_watch) = path_watch {
+            path_watch.abort();
+        }
+        Ok(())
+    }
 
-                        let (mut local_read, mut local_write) = ssh_stream.split();
+    async fn shutdown(&self) {
+        let mut sessions = self.sessions.lock().expect("sessions lock poisoned");
+        sessions.shutting_down = true;
+        for (_, connection) in sessions.active.values() {
+            CloseCode::ShuttingDown.close(connection);
+        }
+    }
+}
 
-                        let a_to_b = async move {
-                            let res = tokio::io::copy(&mut local_read, &mut iroh_send).await;
-                            iroh_send.finish().ok();
-                            res
-                        };
-                        let b_to_a =
-                            async move { tokio::io::copy(&mut iroh_recv, &mut local_write).await };
+/// Connections currently being served, so they can be limited and closed on shutdown.
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    shutting_down: bool,
+    active: HashMap<usize, (EndpointId, Connection)>,
+    /// Open tunnels per peer, across all of its connections.
+    streams: HashMap<EndpointId, usize>,
+}
 
-                        let (_, _) = tokio::join!(a_to_b, b_to_a);
-                    }
-                    Err(e) => {
-                        println!("Failed to connect to SSH server: {e}");
-                    }
+/// Counts a tunnel against its peer's [`Builder::max_sessions_per_peer`] until dropped.
+pub(crate) struct StreamSlot {
+    sessions: Arc<Mutex<Sessions>>,
+    endpoint_id: EndpointId,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let mut sessions = self.sessions.lock().expect("sessions lock poisoned");
+        if let Some(open) = sessions.streams.get_mut(&self.endpoint_id) {
+            *open -= 1;
+            if *open == 0 {
+                sessions.streams.remove(&self.endpoint_id);
+            }
+        }
+    }
+}
+
+impl IrohSsh {
+    fn emit(&self, event: SessionEvent) {
+        // Only fails when nobody is subscribed.
+        self.events.send(event).ok();
+    }
+
+    /// Registers `connection` unless the server is shutting down or the peer is over its limit.
+    pub(crate) fn admit(
+        &self,
+        connection: &Connection,
+        endpoint_id: EndpointId,
+    ) -> Result<(), CloseCode> {
+        let mut sessions = self.sessions.lock().expect("sessions lock poisoned");
+        if sessions.shutting_down {
+            return Err(CloseCode::ShuttingDown);
+        }
+        if self.over_limit(&sessions, endpoint_id) {
+            return Err(CloseCode::RateLimited);
+        }
+        sessions
+            .active
+            .insert(connection.stable_id(), (endpoint_id, connection.clone()));
+        Ok(())
+    }
+
+    /// Counts a new tunnel from `endpoint_id`, or the code to reset it with.
+    pub(crate) fn open_stream(&self, endpoint_id: EndpointId) -> Result<StreamSlot, CloseCode> {
+        let mut sessions = self.sessions.lock().expect("sessions lock poisoned");
+        if sessions.shutting_down {
+            return Err(CloseCode::ShuttingDown);
+        }
+        if self.over_limit(&sessions, endpoint_id) {
+            return Err(CloseCode::RateLimited);
+        }
+        *sessions.streams.entry(endpoint_id).or_default() += 1;
+        Ok(StreamSlot {
+            sessions: self.sessions.clone(),
+            endpoint_id,
+        })
+    }
+
+    /// Forgets a connection [`IrohSsh::admit`] registered, once it's done.
+    pub(crate) fn release(&self, connection: &Connection) {
+        self.sessions
+            .lock()
+            .expect("sessions lock poisoned")
+            .active
+            .remove(&connection.stable_id());
+    }
+
+    fn over_limit(&self, sessions: &Sessions, endpoint_id: EndpointId) -> bool {
+        self.max_sessions_per_peer
+            .is_some_and(|max| sessions.streams.get(&endpoint_id).copied().unwrap_or(0) >= max)
+    }
+
+    async fn serve(
+        &self,
+        connection: &Connection,
+        endpoint_id: EndpointId,
+        mut iroh_send: SendStream,
+        mut iroh_recv: RecvStream,
+    ) -> (SessionBytes, CloseReason) {
+        println!("Accepted bidirectional stream from {endpoint_id}");
+
+        let request = if connection.alpn().as_deref() == Some(&IrohSsh::TARGET_ALPN()) {
+            match target::read_target_name(&mut iroh_recv).await {
+                Ok(name) => TargetRequest::Named(name),
+                Err(e) => {
+                    println!("Failed to read target from {endpoint_id}: {e:#}");
+                    CloseCode::InvalidRequest.reset(&mut iroh_send, &mut iroh_recv);
+                    return (SessionBytes::default(), CloseReason::InvalidRequest);
                 }
             }
+        } else {
+            TargetRequest::Ssh
+        };
+        self.emit(SessionEvent::StreamOpened {
+            remote: endpoint_id,
+            request: request.clone(),
+        });
+
+        let target_stream = match self.connector.connect(endpoint_id, &request).await {
+            Ok(target_stream) => target_stream,
             Err(e) => {
-                println!("Failed to accept bidirectional stream: {e}");
+                self.emit(SessionEvent::TargetDialFailed {
+                    remote: endpoint_id,
+                    request: request.clone(),
+                    error: e.to_string(),
+                });
+                let (code, reason) = match e.kind() {
+                    io::ErrorKind::PermissionDenied => {
+                        println!("Peer {endpoint_id} is not allowed to reach {request}");
+                        (CloseCode::NotAuthorized, CloseReason::NotAllowed)
+                    }
+                    io::ErrorKind::QuotaExceeded => {
+                        println!("Peer {endpoint_id} is rate limited for {request}");
+                        (CloseCode::RateLimited, CloseReason::RateLimited)
+                    }
+                    _ => {
+                        println!("Failed to connect to {request}: {e}");
+                        (CloseCode::TargetUnavailable, CloseReason::TargetUnavailable)
+                    }
+                };
+                code.reset(&mut iroh_send, &mut iroh_recv);
+                return (SessionBytes::default(), reason);
             }
-        }
+        };
 
-        Ok(())
+        println!("Connected {endpoint_id} to {request}");
+        let (mut local_read, mut local_write) = tokio::io::split(target_stream);
+
+        let a_to_b = async move {
+            let res = pump(&mut local_read, &mut iroh_send).await;
+            // Wait for the peer to receive everything before the connection gets dropped.
+            if iroh_send.finish().is_ok() {
+                iroh_send.stopped().await.ok();
+            }
+            res
+        };
+        let b_to_a = async move {
+            let res = pump(&mut iroh_recv, &mut local_write).await;
+            // Pass the peer's EOF on to the target.
+            local_write.shutdown().await.ok();
+            res
+        };
+
+        let (sent, received) = tokio::join!(a_to_b, b_to_a);
+        let bytes = SessionBytes {
+            sent: *sent.as_ref().unwrap_or(&0),
+            received: *received.as_ref().unwrap_or(&0),
+        };
+        let reason = match sent.and(received) {
+            Ok(_) => CloseReason::Finished,
+            Err(e) => CloseReason::ConnectionLost(e.to_string()),
+        };
+        (bytes, reason)
     }
 }
 
-pub fn dot_ssh(
-    default_secret_key: &SecretKey,
-    persist: bool,
-    _service: bool,
-    key_dir: Option<&Path>,
-) -> anyhow::Result<SecretKey> {
-    tracing::info!(
-        "dot_ssh: Function called, persist={}, service={}, key_dir={:?}",
-        persist,
-        _service,
-        key_dir,
-    );
-
+/// The directory persistent keys live in: `key_dir`, or `~/.ssh` (the service account's for services).
+pub(crate) fn key_ssh_dir(_service: bool, key_dir: Option<&Path>) -> Result<PathBuf, Error> {
     #[allow(unused_mut)]
     let mut ssh_dir = if let Some(dir) = key_dir {
         dir.to_path_buf()
     } else {
-        let distro_home = my_home()?.ok_or_else(|| anyhow::anyhow!("home directory not found"))?;
+        let distro_home = my_home().ok().flatten().ok_or_else(|| Error::KeyIo {
+            path: PathBuf::from("~/.ssh"),
+            source: io::Error::new(io::ErrorKind::NotFound, "home directory not found"),
+        })?;
         distro_home.join(".ssh")
     };
 
@@ -389,11 +1255,29 @@ pub fn dot_ssh(
             // Ensure directory exists when running as service
             if !ssh_dir.exists() {
                 tracing::info!("dot_ssh: Service SSH dir doesn't exist, creating it");
-                std::fs::create_dir_all(&ssh_dir)?;
+                std::fs::create_dir_all(&ssh_dir).map_err(Error::key_io(&ssh_dir))?;
             }
         }
     }
 
+    Ok(ssh_dir)
+}
+
+pub fn dot_ssh(
+    default_secret_key: &SecretKey,
+    persist: bool,
+    _service: bool,
+    key_dir: Option<&Path>,
+) -> Result<SecretKey, Error> {
+    tracing::info!(
+        "dot_ssh: Function called, persist={}, service={}, key_dir={:?}",
+        persist,
+        _service,
+        key_dir,
+    );
+
+    let ssh_dir = key_ssh_dir(_service, key_dir)?;
+
     let pub_key = ssh_dir.join("irohssh_ed25519.pub");
     let priv_key = ssh_dir.join("irohssh_ed25519");
 
@@ -407,14 +1291,11 @@ pub fn dot_ssh(
                 "dot_ssh: ssh_dir does not exist and persist=false: {}",
                 ssh_dir.display()
             );
-            bail!(
-                "key directory {} does not exist, use --persist flag to create it",
-                ssh_dir.display()
-            )
+            Err(Error::KeyNotFound { dir: ssh_dir })
         }
         (false, true) => {
             tracing::info!("dot_ssh: Creating ssh_dir: {}", ssh_dir.display());
-            std::fs::create_dir_all(&ssh_dir)?;
+            std::fs::create_dir_all(&ssh_dir).map_err(Error::key_io(&ssh_dir))?;
             println!("[INFO] created .ssh folder: {}", ssh_dir.display());
             dot_ssh(default_secret_key, persist, _service, key_dir)
         }
@@ -426,14 +1307,7 @@ pub fn dot_ssh(
             // check pub and priv key already exists
             if pub_key.exists() && priv_key.exists() {
                 tracing::info!("dot_ssh: Keys exist, reading them");
-                // read secret key
-                if let Ok(secret_key) = std::fs::read(priv_key.clone()) {
-                    let mut sk_bytes = [0u8; SECRET_KEY_LENGTH];
-                    sk_bytes.copy_from_slice(z32::decode(secret_key.as_slice())?.as_slice());
-                    Ok(SecretKey::from_bytes(&sk_bytes))
-                } else {
-                    bail!("failed to read secret key from {}", priv_key.display())
-                }
+                read_secret_key(&priv_key)
             } else {
                 tracing::info!("dot_ssh: Keys don't exist, creating new keys");
                 tracing::debug!("dot_ssh: Writing to pub_key: {}", pub_key.display());
@@ -452,7 +1326,10 @@ pub fn dot_ssh(
                             e,
                             e.kind()
                         );
-                        return Err(e.into());
+                        return Err(Error::KeyIo {
+                            path: pub_key,
+                            source: e,
+                        });
                     }
                 }
 
@@ -466,7 +1343,10 @@ pub fn dot_ssh(
                             e,
                             e.kind()
                         );
-                        return Err(e.into());
+                        return Err(Error::KeyIo {
+                            path: priv_key,
+                            source: e,
+                        });
                     }
                 }
 
@@ -476,21 +1356,30 @@ pub fn dot_ssh(
         (true, false) => {
             // check pub and priv key already exists
             if pub_key.exists() && priv_key.exists() {
-                // read secret key
-                if let Ok(secret_key) = std::fs::read(priv_key.clone()) {
-                    let mut sk_bytes = [0u8; SECRET_KEY_LENGTH];
-                    sk_bytes.copy_from_slice(z32::decode(secret_key.as_slice())?.as_slice());
-                    return Ok(SecretKey::from_bytes(&sk_bytes));
-                }
+                return read_secret_key(&priv_key);
             }
-            bail!(
-                "no iroh-ssh keys found in {}, use --persist flag to create it",
-                ssh_dir.display()
-            )
+            Err(Error::KeyNotFound { dir: ssh_dir })
         }
     }
 }
 
+fn read_secret_key(path: &Path) -> Result<SecretKey, Error> {
+    let encoded = std::fs::read(path).map_err(Error::key_io(path))?;
+    let decoded = z32::decode(encoded.trim_ascii()).map_err(|e| Error::KeyDecode {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+    let sk_bytes: [u8; SECRET_KEY_LENGTH] =
+        decoded
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::KeyDecode {
+                path: path.to_path_buf(),
+                reason: format!("expected {SECRET_KEY_LENGTH} bytes, got {}", decoded.len()),
+            })?;
+    Ok(SecretKey::from_bytes(&sk_bytes))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -503,6 +1392,273 @@ mod tests {
             .collect()
     }
 
+    fn loopback_addr(endpoint: &Endpoint) -> EndpointAddr {
+        EndpointAddr::from_parts(
+            endpoint.id(),
+            endpoint
+                .bound_sockets()
+                .into_iter()
+                .filter(|addr| addr.is_ipv4())
+                .map(|addr| {
+                    iroh::TransportAddr::Ip(SocketAddr::from((Ipv4Addr::LOCALHOST, addr.port())))
+                }),
+        )
+    }
+
+    /// A server built from `server` accepting on loopback, a client, and the server's address.
+    async fn pair(mut server: Builder) -> (IrohSsh, IrohSsh, EndpointAddr) {
+        let server_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let mut server = server.build_on(server_endpoint.clone()).await.unwrap();
+        server.router = Some(
+            server
+                .accept_on(Router::builder(server_endpoint.clone()))
+                .spawn(),
+        );
+
+        let client_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let client = IrohSsh::builder().build_on(client_endpoint).await.unwrap();
+        (server, client, loopback_addr(&server_endpoint))
+    }
+
+    #[tokio::test]
+    async fn open_reaches_target_connector() {
+        use tokio::io::AsyncReadExt as _;
+
+        let (connector, mut rx) = crate::MemoryConnector::new(1024);
+        let (_server, client, server_addr) =
+            pair(IrohSsh::builder().target_connector(connector)).await;
+
+        let request = TargetRequest::Named("api".to_string());
+        let mut tunnel = client.open(server_addr, &request).await.unwrap();
+        tunnel.write_all(b"ping").await.unwrap();
+
+        let mut app = rx.recv().await.unwrap();
+        assert_eq!(app.remote, client.endpoint_id());
+        assert_eq!(app.request, request);
+        let mut buf = [0u8; 4];
+        app.stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        app.stream.write_all(b"pong").await.unwrap();
+        tunnel.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn open_on_reuses_connection() {
+        use tokio::io::AsyncReadExt as _;
+
+        let (connector, mut rx) = crate::MemoryConnector::new(1024);
+        let (_server, client, server_addr) = pair(
+            IrohSsh::builder()
+                .target_connector(connector)
+                .max_sessions_per_peer(2),
+        )
+        .await;
+
+        let request = TargetRequest::Named("api".to_string());
+        let mut first = client.open(server_addr, &request).await.unwrap();
+        let mut second = IrohSsh::open_on(first.connection().clone(), &request)
+            .await
+            .unwrap();
+        assert_eq!(
+            first.connection().stable_id(),
+            second.connection().stable_id()
+        );
+
+        for (tunnel, msg) in [(&mut first, b"one"), (&mut second, b"two")] {
+            tunnel.write_all(msg).await.unwrap();
+            let mut app = rx.recv().await.unwrap();
+            let mut buf = [0u8; 3];
+            app.stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, msg);
+        }
+
+        // the limit counts tunnels, and only the tunnel over it is reset
+        let mut third = IrohSsh::open_on(first.connection().clone(), &request)
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        assert!(third.read_to_end(&mut buf).await.is_err());
+        assert_eq!(third.close_code(), Some(CloseCode::RateLimited));
+        first.write_all(b"one").await.unwrap();
+        assert!(first.connection().close_reason().is_none());
+    }
+
+    #[tokio::test]
+    async fn accepted_session_emits_events() {
+        use tokio::io::AsyncReadExt as _;
+
+        let (connector, mut rx) = crate::MemoryConnector::new(1024);
+        let (server, client, server_addr) =
+            pair(IrohSsh::builder().target_connector(connector)).await;
+        let mut events = server.subscribe();
+
+        let mut tunnel = client.open(server_addr, &TargetRequest::Ssh).await.unwrap();
+        tunnel.write_all(b"ping").await.unwrap();
+        tunnel.shutdown().await.unwrap();
+
+        let mut app = rx.recv().await.unwrap();
+        let mut buf = [0u8; 4];
+        app.stream.read_exact(&mut buf).await.unwrap();
+        app.stream.write_all(b"pong!").await.unwrap();
+        drop(app);
+        let mut reply = [0u8; 5];
+        tunnel.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"pong!");
+
+        let mut seen = Vec::new();
+        loop {
+            let event = events.recv().await.unwrap();
+            if let SessionEvent::SessionClosed { bytes, reason, .. } = &event {
+                assert_eq!(
+                    *bytes,
+                    SessionBytes {
+                        sent: 5,
+                        received: 4
+                    }
+                );
+                assert_eq!(*reason, CloseReason::Finished);
+                break;
+            }
+            seen.push(event);
+        }
+        assert!(matches!(
+            seen.first(),
+            Some(SessionEvent::PeerConnected { remote }) if *remote == client.endpoint_id()
+        ));
+        assert!(seen.iter().any(|event| matches!(
+            event,
+            SessionEvent::StreamOpened {
+                request: TargetRequest::Ssh,
+                ..
+            }
+        )));
+    }
+
+    #[tokio::test]
+    async fn addr_cache_seeds_connect_by_id() {
+        let (connector, _rx) = crate::MemoryConnector::new(1024);
+        let (server, _, server_addr) = pair(IrohSsh::builder().target_connector(connector)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AddrCache::in_dir(dir.path());
+        let client = |cache: AddrCache| async move {
+            let client_endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+                .bind()
+                .await
+                .unwrap();
+            IrohSsh::builder()
+                .addr_cache(cache)
+                .build_on(client_endpoint)
+                .await
+                .unwrap()
+        };
+
+        // the first connection is given the address and caches the direct path
+        let _tunnel = client(cache.clone())
+            .await
+            .open(server_addr.clone(), &TargetRequest::Ssh)
+            .await
+            .unwrap();
+        let cached = cache.get(&server.endpoint_id()).unwrap();
+        assert_eq!(
+            cached.ip_addrs().collect::<Vec<_>>(),
+            server_addr.ip_addrs().collect::<Vec<_>>()
+        );
+
+        // Without the cache there is no discovery to find the server by id.
+        client(cache)
+            .await
+            .open(server.endpoint_id(), &TargetRequest::Ssh)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_tunnel_reports_close_code() {
+        use tokio::io::AsyncReadExt as _;
+
+        let (_server, client, server_addr) = pair(IrohSsh::builder()).await;
+
+        let mut tunnel = client
+            .open(server_addr, &TargetRequest::Named("nas".to_string()))
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        assert!(tunnel.read_to_end(&mut buf).await.is_err());
+        assert_eq!(tunnel.close_code(), Some(CloseCode::NotAuthorized));
+
+        // the connection stays open for other tunnels
+        assert!(tunnel.connection().close_reason().is_none());
+        let mut again = IrohSsh::open_on(
+            tunnel.connection().clone(),
+            &TargetRequest::Named("backup".to_string()),
+        )
+        .await
+        .unwrap();
+        assert!(again.read_to_end(&mut buf).await.is_err());
+        assert_eq!(again.close_code(), Some(CloseCode::NotAuthorized));
+    }
+
+    #[tokio::test]
+    async fn relay_only_stays_off_the_lan() {
+        let iroh_ssh = IrohSsh::builder()
+            .accept_incoming(false)
+            .discovery(vec![DiscoveryBackend::None])
+            .relay_urls(vec!["http://127.0.0.1:9".parse().unwrap()])
+            .bind_addr("0.0.0.0:0".parse().unwrap())
+            .path_policy(PathPolicy::RelayOnly)
+            .build()
+            .await
+            .unwrap();
+        let endpoint = iroh_ssh.endpoint();
+
+        // nothing a peer on another host could punch a hole to or from
+        assert!(
+            endpoint
+                .bound_sockets()
+                .iter()
+                .all(|addr| addr.ip().is_loopback())
+        );
+        let mut addr = endpoint.addr();
+        for _ in 0..50 {
+            if addr.ip_addrs().next().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            addr = endpoint.addr();
+        }
+        assert!(addr.ip_addrs().next().is_some());
+        assert!(addr.ip_addrs().all(|addr| addr.ip().is_loopback()));
+        endpoint.close().await;
+    }
+
+    #[test]
+    fn dot_ssh_reports_typed_key_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = SecretKey::generate(&mut rand::rng());
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            dot_ssh(&key, false, false, Some(&missing)),
+            Err(Error::KeyNotFound { .. })
+        ));
+
+        std::fs::write(dir.path().join("irohssh_ed25519.pub"), "x").unwrap();
+        std::fs::write(dir.path().join("irohssh_ed25519"), "not a key").unwrap();
+        assert!(matches!(
+            dot_ssh(&key, false, false, Some(dir.path())),
+            Err(Error::KeyDecode { .. })
+        ));
+    }
+
     #[test]
     fn login_user_flag_is_passed_to_ssh() {
         let opts = SshOpts {
@@ -517,6 +1673,8 @@ mod tests {
             Vec::new(),
             &[],
             &[],
+            &ProxyOpts::default(),
+            None,
         );
 
         let args = args_of(&cmd);
@@ -524,6 +1682,101 @@ mod tests {
         assert_eq!(args[l_pos + 1], "alice");
     }
 
+    #[test]
+    fn retry_flags_are_passed_to_proxy() {
+        let cli = crate::cli::Cli::try_parse_from([
+            "iroh-ssh",
+            "-v",
+            "--connect-timeout",
+            "5",
+            "--retries",
+            "3",
+            "--wait-for-host",
+            "endpoint123",
+        ])
+        .unwrap();
+
+        let cmd = build_ssh_command(
+            Path::new("/usr/bin/iroh-ssh"),
+            cli.target.unwrap(),
+            cli.ssh,
+            Vec::new(),
+            &[],
+            &[],
+            &cli.proxy,
+            None,
+        );
+        let args = args_of(&cmd);
+        assert_eq!(
+            args[1],
+            "ProxyCommand=/usr/bin/iroh-ssh proxy --connect-timeout 5 --retries 3 --wait-for-host --verbose %h:%p"
+        );
+    }
+
+    #[test]
+    fn proxy_command_quotes_paths() {
+        let cli = crate::cli::Cli::try_parse_from([
+            "iroh-ssh",
+            "--relay-map",
+            "/home/me/My Relays/100%.toml",
+            "endpoint123",
+        ])
+        .unwrap();
+
+        let cmd = build_ssh_command(
+            Path::new("/home/me/it's bin/iroh-ssh"),
+            cli.target.unwrap(),
+            cli.ssh,
+            Vec::new(),
+            &[],
+            &[],
+            &cli.proxy,
+            None,
+        );
+        let args = args_of(&cmd);
+        assert_eq!(
+            args[1],
+            r"ProxyCommand='/home/me/it'\''s bin/iroh-ssh' proxy --relay-map '/home/me/My Relays/100%%.toml' %h:%p"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mux_adds_control_master_options() {
+        let cli = crate::cli::Cli::try_parse_from([
+            "iroh-ssh",
+            "--mux",
+            "--mux-persist",
+            "1h",
+            "endpoint123",
+        ])
+        .unwrap();
+        assert!(cli.ssh.mux);
+
+        let cmd = build_ssh_command(
+            Path::new("/usr/bin/iroh-ssh"),
+            cli.target.unwrap(),
+            cli.ssh,
+            Vec::new(),
+            &[],
+            &[],
+            &cli.proxy,
+            Some(Path::new("/run/user/1000/iroh-ssh/mux/%C")),
+        );
+        let args = args_of(&cmd);
+        assert_eq!(
+            &args[2..8],
+            [
+                "-o",
+                "ControlMaster=auto",
+                "-o",
+                "ControlPath=/run/user/1000/iroh-ssh/mux/%C",
+                "-o",
+                "ControlPersist=1h"
+            ]
+        );
+    }
+
     #[test]
     fn rsync_invocation_parses_and_builds() {
         // Mirrors `rsync -e iroh-ssh /local user@<id>:/remote`, which invokes:
@@ -560,6 +1813,8 @@ mod tests {
             remote_cmd_raw,
             &[],
             &[],
+            &ProxyOpts::default(),
+            None,
         );
         let args = args_of(&cmd);
 
@@ -0,0 +1,104 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+};
+
+/// The host the server dials sshd on, see `--ssh-host`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SshHost {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl Default for SshHost {
+    fn default() -> Self {
+        SshHost::Ip(Ipv4Addr::LOCALHOST.into())
+    }
+}
+
+impl SshHost {
+    /// The address to probe and dial sshd at.
+    ///
+    /// A name is looked up once with the system resolver and its first answer
+    /// is kept, so availability checks and tunnels never disagree on the address.
+    pub async fn resolve(&self, port: u16) -> io::Result<IpAddr> {
+        match self {
+            SshHost::Ip(ip) => Ok(*ip),
+            SshHost::Name(name) => tokio::net::lookup_host((name.as_str(), port))
+                .await?
+                .next()
+                .map(|addr| addr.ip())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("'{name}' did not resolve to any address"),
+                    )
+                }),
+        }
+    }
+}
+
+impl FromStr for SshHost {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unbracketed = s
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(s);
+        if let Ok(ip) = unbracketed.parse() {
+            return Ok(SshHost::Ip(ip));
+        }
+        if s.is_empty()
+            || !s
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        {
+            anyhow::bail!("invalid ssh host '{s}', expected an IP address, [IPV6] or a hostname");
+        }
+        Ok(SshHost::Name(s.to_string()))
+    }
+}
+
+impl fmt::Display for SshHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SshHost::Ip(IpAddr::V6(ip)) => write!(f, "[{ip}]"),
+            SshHost::Ip(ip) => write!(f, "{ip}"),
+            SshHost::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    #[tokio::test]
+    async fn ssh_hosts_parse_and_resolve() {
+        for text in ["127.0.0.1", "[::1]", "sshd.internal"] {
+            assert_eq!(SshHost::from_str(text).unwrap().to_string(), text);
+        }
+        assert_eq!(
+            SshHost::from_str("::1").unwrap(),
+            SshHost::Ip(Ipv6Addr::LOCALHOST.into())
+        );
+        assert!(SshHost::from_str("").is_err());
+        assert!(SshHost::from_str("host:22").is_err());
+
+        let ip = SshHost::from_str("[::1]")
+            .unwrap()
+            .resolve(22)
+            .await
+            .unwrap();
+        assert_eq!(ip, IpAddr::from(Ipv6Addr::LOCALHOST));
+        let ip = SshHost::from_str("localhost")
+            .unwrap()
+            .resolve(22)
+            .await
+            .unwrap();
+        assert!(ip.is_loopback());
+    }
+}
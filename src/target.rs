@@ -0,0 +1,315 @@
+use std::{collections::BTreeMap, fmt, io, path::PathBuf, str::FromStr};
+
+use anyhow::bail;
+use iroh::{
+    EndpointId,
+    endpoint::{RecvStream, SendStream},
+};
+use tokio::{io::AsyncReadExt as _, net::TcpStream};
+
+use crate::connector::TargetStream;
+
+/// Longest target name a client may request.
+pub const MAX_TARGET_NAME_LEN: usize = 64;
+
+/// Default baud rate for `serial:` targets that don't specify one.
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// Where a named target lives: a TCP host:port, a Unix socket or a tty device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetAddr {
+    Tcp(String),
+    Unix(PathBuf),
+    Serial { path: PathBuf, baud: u32 },
+}
+
+impl TargetAddr {
+    pub(crate) async fn connect(&self) -> io::Result<Box<dyn TargetStream>> {
+        match self {
+            TargetAddr::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr).await?)),
+            #[cfg(unix)]
+            TargetAddr::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+            #[cfg(unix)]
+            TargetAddr::Serial { path, baud } => {
+                Ok(Box::new(crate::serial::SerialPort::open(path, *baud)?))
+            }
+            #[cfg(not(unix))]
+            TargetAddr::Unix(_) | TargetAddr::Serial { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket and serial targets are only supported on unix",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetAddr::Tcp(addr) => write!(f, "{addr}"),
+            TargetAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            TargetAddr::Serial { path, baud } => write!(f, "serial:{}@{baud}", path.display()),
+        }
+    }
+}
+
+impl FromStr for TargetAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                bail!("invalid target address '{s}', expected unix:PATH");
+            }
+            return Ok(TargetAddr::Unix(PathBuf::from(path)));
+        }
+        if let Some(device) = s.strip_prefix("serial:") {
+            let (path, baud) = match device.rsplit_once('@') {
+                Some((path, baud)) => (
+                    path,
+                    u32::from_str(baud)
+                        .map_err(|e| anyhow::anyhow!("invalid baud rate in '{s}': {e}"))?,
+                ),
+                None => (device, DEFAULT_BAUD),
+            };
+            if path.is_empty() {
+                bail!("invalid target address '{s}', expected serial:DEVICE[@BAUD]");
+            }
+            return Ok(TargetAddr::Serial {
+                path: PathBuf::from(path),
+                baud,
+            });
+        }
+        if s.rsplit_once(':')
+            .is_none_or(|(host, port)| host.is_empty() || u16::from_str(port).is_err())
+        {
+            bail!(
+                "invalid target address '{s}', expected HOST:PORT, unix:PATH or serial:DEVICE[@BAUD]"
+            );
+        }
+        Ok(TargetAddr::Tcp(s.to_string()))
+    }
+}
+
+/// A named address on the server that peers can be forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSpec {
+    pub name: String,
+    pub addr: TargetAddr,
+}
+
+impl FromStr for TargetSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, addr) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("invalid target '{s}', expected NAME=ADDRESS"))?;
+        validate_target_name(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            addr: TargetAddr::from_str(addr)?,
+        })
+    }
+}
+
+impl fmt::Display for TargetSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.addr)
+    }
+}
+
+/// Allows one peer (or every peer with `*`) to use a named target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowRule {
+    pub name: String,
+    pub peer: Option<EndpointId>,
+}
+
+impl FromStr for AllowRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, peer) = s.split_once('=').ok_or_else(|| {
+            anyhow::anyhow!("invalid allow rule '{s}', expected NAME=ENDPOINT_ID")
+        })?;
+        validate_target_name(name)?;
+        let peer = match peer {
+            "*" => None,
+            id => Some(
+                EndpointId::from_str(id)
+                    .map_err(|e| anyhow::anyhow!("invalid endpoint id in allow rule '{s}': {e}"))?,
+            ),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            peer,
+        })
+    }
+}
+
+impl fmt::Display for AllowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.peer {
+            Some(peer) => write!(f, "{}={peer}", self.name),
+            None => write!(f, "{}=*", self.name),
+        }
+    }
+}
+
+/// Named targets the server forwards to, each only reachable by its allowed peers.
+#[derive(Debug, Clone, Default)]
+pub struct Targets {
+    targets: BTreeMap<String, Target>,
+}
+
+#[derive(Debug, Clone)]
+struct Target {
+    addr: TargetAddr,
+    allow_all: bool,
+    allowed: Vec<EndpointId>,
+}
+
+impl Targets {
+    pub fn new(specs: Vec<TargetSpec>, rules: Vec<AllowRule>) -> anyhow::Result<Self> {
+        let mut targets = BTreeMap::new();
+        for spec in specs {
+            let target = Target {
+                addr: spec.addr,
+                allow_all: false,
+                allowed: Vec::new(),
+            };
+            if targets.insert(spec.name.clone(), target).is_some() {
+                bail!("target '{}' is defined more than once", spec.name);
+            }
+        }
+        for rule in rules {
+            let Some(target) = targets.get_mut(&rule.name) else {
+                bail!("allow rule '{rule}' refers to an unknown target");
+            };
+            match rule.peer {
+                Some(peer) => target.allowed.push(peer),
+                None => target.allow_all = true,
+            }
+        }
+        Ok(Self { targets })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Returns the address behind `name` if `peer` may use it.
+    pub fn resolve(&self, name: &str, peer: &EndpointId) -> Option<&TargetAddr> {
+        let target = self.targets.get(name)?;
+        (target.allow_all || target.allowed.contains(peer)).then_some(&target.addr)
+    }
+}
+
+pub(crate) fn validate_target_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || name.len() > MAX_TARGET_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        bail!("invalid target name '{name}', use up to {MAX_TARGET_NAME_LEN} of [A-Za-z0-9._-]");
+    }
+    Ok(())
+}
+
+/// Sends the requested target name as the first bytes of the stream.
+pub(crate) async fn write_target_name(send: &mut SendStream, name: &str) -> io::Result<()> {
+    validate_target_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    send.write_all(&[name.len() as u8]).await?;
+    send.write_all(name.as_bytes()).await?;
+    Ok(())
+}
+
+pub(crate) async fn read_target_name(recv: &mut RecvStream) -> anyhow::Result<String> {
+    let len = recv.read_u8().await? as usize;
+    if len == 0 || len > MAX_TARGET_NAME_LEN {
+        bail!("invalid target name length {len}");
+    }
+    let mut name = vec![0u8; len];
+    recv.read_exact(&mut name).await?;
+    let name = String::from_utf8(name)?;
+    validate_target_name(&name)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    fn targets_respect_allow_rules() {
+        let alice = SecretKey::generate(&mut rand::rng()).public();
+        let bob = SecretKey::generate(&mut rand::rng()).public();
+
+        let targets = Targets::new(
+            vec![
+                "nas=192.168.1.10:22".parse().unwrap(),
+                "printer=192.168.1.50:631".parse().unwrap(),
+            ],
+            vec![
+                format!("nas={alice}").parse().unwrap(),
+                "printer=*".parse().unwrap(),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            targets.resolve("nas", &alice),
+            Some(&TargetAddr::Tcp("192.168.1.10:22".to_string()))
+        );
+        assert_eq!(targets.resolve("nas", &bob), None);
+        assert_eq!(
+            targets.resolve("printer", &bob),
+            Some(&TargetAddr::Tcp("192.168.1.50:631".to_string()))
+        );
+        assert_eq!(targets.resolve("scanner", &alice), None);
+    }
+
+    #[test]
+    fn target_addr_kinds_parse() {
+        assert_eq!(
+            TargetAddr::from_str("unix:/run/podman/sshd.sock").unwrap(),
+            TargetAddr::Unix(PathBuf::from("/run/podman/sshd.sock"))
+        );
+        assert_eq!(
+            TargetAddr::from_str("serial:/dev/ttyUSB0@9600").unwrap(),
+            TargetAddr::Serial {
+                path: PathBuf::from("/dev/ttyUSB0"),
+                baud: 9600
+            }
+        );
+        assert_eq!(
+            TargetAddr::from_str("serial:/dev/ttyS0")
+                .unwrap()
+                .to_string(),
+            "serial:/dev/ttyS0@115200"
+        );
+        // services pass targets and rules on as text
+        for text in [
+            "nas=[fe80::1]:22",
+            "api=unix:/run/api.sock",
+            "tty=serial:/dev/ttyS0@9600",
+        ] {
+            assert_eq!(TargetSpec::from_str(text).unwrap().to_string(), text);
+        }
+        assert_eq!(AllowRule::from_str("nas=*").unwrap().to_string(), "nas=*");
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        assert!(TargetSpec::from_str("nas").is_err());
+        assert!(TargetSpec::from_str("nas=192.168.1.10").is_err());
+        assert!(TargetSpec::from_str("n@s=192.168.1.10:22").is_err());
+        assert!(TargetSpec::from_str("nas=[fe80::1]:22").is_ok());
+        assert!(TargetSpec::from_str("tty=serial:/dev/ttyUSB0@fast").is_err());
+        assert!(TargetSpec::from_str("api=unix:").is_err());
+        assert!(AllowRule::from_str("nas=not-an-id").is_err());
+        assert!(Targets::new(Vec::new(), vec!["nas=*".parse().unwrap()]).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr as _,
+};
+
+use iroh::Watcher as _;
+use iroh_tickets::endpoint::EndpointTicket;
+
+use crate::IrohSsh;
+
+pub const TICKET_FILE_NAME: &str = "irohssh_ticket";
+
+/// Where a server with persistent keys in `ssh_dir` publishes its current ticket.
+pub(crate) fn ticket_file(ssh_dir: &Path) -> PathBuf {
+    ssh_dir.join(TICKET_FILE_NAME)
+}
+
+/// The ticket last written to `path`, if any.
+pub(crate) fn read_ticket(path: &Path) -> Option<EndpointTicket> {
+    let text = std::fs::read_to_string(path).ok()?;
+    EndpointTicket::from_str(text.trim()).ok()
+}
+
+fn write_ticket(path: &Path, ticket: &EndpointTicket) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    io::Write::write_all(&mut tmp, format!("{ticket}\n").as_bytes())?;
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+impl IrohSsh {
+    /// A ticket with this endpoint's id, home relay and current direct addresses.
+    pub fn ticket(&self) -> EndpointTicket {
+        EndpointTicket::new(self.endpoint.addr())
+    }
+
+    /// Rewrites the ticket at `path` whenever the endpoint's addresses change, so
+    /// `iroh-ssh info` can print it. Runs until the endpoint is closed.
+    pub(crate) async fn publish_ticket(&self, path: PathBuf) {
+        let mut addrs = self.endpoint.watch_addr();
+        loop {
+            if let Err(e) = write_ticket(&path, &EndpointTicket::new(addrs.get())) {
+                tracing::warn!("failed to write ticket to {}: {e}", path.display());
+            }
+            if addrs.updated().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::{EndpointAddr, SecretKey};
+
+    #[test]
+    fn ticket_file_roundtrips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = ticket_file(dir.path());
+        assert!(read_ticket(&path).is_none());
+
+        let addr = EndpointAddr::new(SecretKey::generate(&mut rand::rng()).public())
+            .with_ip_addr("192.168.1.5:40000".parse().unwrap())
+            .with_relay_url("https://relay.example.com".parse().unwrap());
+        write_ticket(&path, &EndpointTicket::new(addr.clone())).unwrap();
+        assert_eq!(read_ticket(&path).unwrap().endpoint_addr(), &addr);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+use iroh::endpoint::{TransportConfig, VarInt};
+use quinn::{
+    IdleTimeout,
+    congestion::{BbrConfig, CubicConfig, NewRenoConfig},
+};
+
+/// iroh's keep-alive, which keeps NAT mappings of idle connections open.
+const IROH_KEEP_ALIVE: Duration = Duration::from_secs(1);
+
+/// The QUIC congestion controller, see `--quic-congestion`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CongestionControl {
+    #[default]
+    Cubic,
+    NewReno,
+    /// Keeps long, lossy links busier than loss based controllers, experimental in quinn.
+    Bbr,
+}
+
+impl FromStr for CongestionControl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cubic" => Ok(CongestionControl::Cubic),
+            "new-reno" => Ok(CongestionControl::NewReno),
+            "bbr" => Ok(CongestionControl::Bbr),
+            _ => anyhow::bail!(
+                "unknown congestion controller '{s}', expected cubic, new-reno or bbr"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CongestionControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CongestionControl::Cubic => "cubic",
+            CongestionControl::NewReno => "new-reno",
+            CongestionControl::Bbr => "bbr",
+        })
+    }
+}
+
+/// A window size in bytes, written with an optional K, M or G suffix (powers of 1024).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+const UNITS: [(char, u64); 3] = [('G', 1 << 30), ('M', 1 << 20), ('K', 1 << 10)];
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || anyhow::anyhow!("invalid size '{s}', expected bytes or a number with K, M or G");
+        let (digits, unit) = match s.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (
+                &s[..i],
+                UNITS
+                    .iter()
+                    .find(|(suffix, _)| c.eq_ignore_ascii_case(suffix))
+                    .ok_or_else(invalid)?
+                    .1,
+            ),
+            _ => (s, 1),
+        };
+        let n: u64 = digits.parse().map_err(|_| invalid())?;
+        n.checked_mul(unit).map(ByteSize).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match UNITS
+            .iter()
+            .find(|(_, unit)| self.0 != 0 && self.0.is_multiple_of(*unit))
+        {
+            Some((suffix, unit)) => write!(f, "{}{suffix}", self.0 / unit),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Overrides of iroh's QUIC transport config, unset fields keep iroh's values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct QuicTuning {
+    pub(crate) stream_receive_window: Option<u64>,
+    pub(crate) receive_window: Option<u64>,
+    pub(crate) send_window: Option<u64>,
+    pub(crate) congestion: Option<CongestionControl>,
+    /// `Some(None)` turns keep-alives off.
+    pub(crate) keep_alive_interval: Option<Option<Duration>>,
+    /// `Some(None)` never times out idle connections.
+    pub(crate) max_idle_timeout: Option<Option<Duration>>,
+}
+
+impl QuicTuning {
+    /// The transport config to bind the endpoint with, `None` when nothing is overridden.
+    pub(crate) fn transport_config(&self) -> Option<TransportConfig> {
+        if *self == Self::default() {
+            return None;
+        }
+        // start from what iroh would use
+        let mut config = TransportConfig::default();
+        config.keep_alive_interval(Some(IROH_KEEP_ALIVE));
+
+        if let Some(window) = self.stream_receive_window {
+            config.stream_receive_window(var_int(window));
+        }
+        if let Some(window) = self.receive_window {
+            config.receive_window(var_int(window));
+        }
+        if let Some(window) = self.send_window {
+            config.send_window(window);
+        }
+        match self.congestion {
+            Some(CongestionControl::Cubic) => {
+                config.congestion_controller_factory(Arc::new(CubicConfig::default()))
+            }
+            Some(CongestionControl::NewReno) => {
+                config.congestion_controller_factory(Arc::new(NewRenoConfig::default()))
+            }
+            Some(CongestionControl::Bbr) => {
+                config.congestion_controller_factory(Arc::new(BbrConfig::default()))
+            }
+            None => &mut config,
+        };
+        if let Some(interval) = self.keep_alive_interval {
+            config.keep_alive_interval(interval);
+        }
+        if let Some(timeout) = self.max_idle_timeout {
+            config.max_idle_timeout(timeout.map(|timeout| {
+                IdleTimeout::try_from(timeout).unwrap_or(IdleTimeout::from(VarInt::MAX))
+            }));
+        }
+        Some(config)
+    }
+}
+
+/// QUIC can't announce windows above 2^62, which is as good as unlimited.
+fn var_int(n: u64) -> VarInt {
+    VarInt::from_u64(n).unwrap_or(VarInt::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quic_tuning_parses_and_applies() {
+        for text in ["0", "1000", "512K", "16M", "1G"] {
+            assert_eq!(ByteSize::from_str(text).unwrap().to_string(), text);
+        }
+        assert_eq!(ByteSize::from_str("4m").unwrap(), ByteSize(4 << 20));
+        assert_eq!(ByteSize::from_str("2048").unwrap().to_string(), "2K");
+        for text in ["", "M", "1.5M", "16MB", "-1", "99999999999G"] {
+            assert!(ByteSize::from_str(text).is_err(), "{text}");
+        }
+        for text in ["cubic", "new-reno", "bbr"] {
+            assert_eq!(CongestionControl::from_str(text).unwrap().to_string(), text);
+        }
+        assert!(CongestionControl::from_str("vegas").is_err());
+
+        assert!(QuicTuning::default().transport_config().is_none());
+        let tuning = QuicTuning {
+            stream_receive_window: Some(16 << 20),
+            receive_window: Some(u64::MAX),
+            congestion: Some(CongestionControl::Bbr),
+            keep_alive_interval: Some(None),
+            max_idle_timeout: Some(Some(Duration::from_secs(120))),
+            ..Default::default()
+        };
+        let config = format!("{:?}", tuning.transport_config().unwrap());
+        assert!(
+            config.contains("stream_receive_window: 16777216"),
+            "{config}"
+        );
+        assert!(config.contains("keep_alive_interval: None"), "{config}");
+        assert!(
+            config.contains("max_idle_timeout: Some(120000)"),
+            "{config}"
+        );
+    }
+}
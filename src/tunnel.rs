@@ -0,0 +1,100 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use n0_future::task::AbortOnDropHandle;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::CloseCode;
+
+/// A bidirectional stream to a server-side target, returned by [`crate::IrohSsh::open`].
+///
+/// Shutting down the write half finishes the QUIC send stream, which the server
+/// forwards to the target as EOF.
+#[derive(Debug)]
+pub struct Tunnel {
+    connection: Connection,
+    send: SendStream,
+    recv: RecvStream,
+    /// The code the server reset this tunnel with, seen while reading or writing it.
+    reset: Option<CloseCode>,
+    /// Caches the path once it turns direct, for as long as the tunnel is open.
+    _path_watch: Option<AbortOnDropHandle<()>>,
+}
+
+impl Tunnel {
+    pub(crate) fn new(connection: Connection, send: SendStream, recv: RecvStream) -> Self {
+        Self {
+            connection,
+            send,
+            recv,
+            reset: None,
+            _path_watch: None,
+        }
+    }
+
+    pub(crate) fn with_path_watch(mut self, path_watch: AbortOnDropHandle<()>) -> Self {
+        self._path_watch = Some(path_watch);
+        self
+    }
+
+    /// The iroh connection this tunnel runs over.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Why the server closed the tunnel or its connection, if it did so with a [`CloseCode`].
+    ///
+    /// A tunnel closed on its own is only noticed once reading or writing it failed.
+    pub fn close_code(&self) -> Option<CloseCode> {
+        CloseCode::from_connection(&self.connection).or(self.reset)
+    }
+
+    fn record<T>(&mut self, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if let Poll::Ready(Err(err)) = &poll
+            && let Some(code) = CloseCode::from_stream_error(err)
+        {
+            self.reset = Some(code);
+        }
+        poll
+    }
+
+    pub fn into_parts(self) -> (Connection, SendStream, RecvStream) {
+        (self.connection, self.send, self.recv)
+    }
+}
+
+impl AsyncRead for Tunnel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.recv).poll_read(cx, buf);
+        self.record(poll)
+    }
+}
+
+impl AsyncWrite for Tunnel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf);
+        self.record(poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.send).poll_flush(cx);
+        self.record(poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.send).poll_shutdown(cx);
+        self.record(poll)
+    }
+}